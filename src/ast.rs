@@ -15,6 +15,7 @@ pub enum Stmt<'a> {
     Return(Return<'a>),
     Block(Block<'a>),
     PutAt(PutAt<'a>),
+    Constraint(Constraint<'a>),
 }
 
 pub struct Import<'a>(pub Idents<'a>);
@@ -25,15 +26,18 @@ pub struct Assign<'a>(pub &'a str, pub Term<'a>);
 
 pub enum Term<'a> {
     String(String),
+    PrefixedString(&'a str, String),
     Number(Num),
     Color(Color),
     Idents(Idents<'a>),
     Coord(Box<Coord<'a>>),
+    List(Vec<Term<'a>>),
     BinOp(Box<BinTerm<'a>>),
     UnOp(Box<UnTerm<'a>>),
     FnCall(Box<FnCall<'a>>),
     FnDef(FnDef<'a>),
     Block(Block<'a>),
+    Layout(Block<'a>),
 }
 
 pub struct Num(pub f64, pub Option<Unit>);
@@ -88,6 +92,20 @@ pub struct Return<'a>(pub Term<'a>);
 
 pub struct PutAt<'a>(pub Term<'a>, pub Term<'a>);
 
+/// A relation between edges of frames in a layout block, such as
+/// `label.left == box.right + 1em`.
+pub struct Constraint<'a>(pub Term<'a>, pub Relation, pub Term<'a>);
+
+#[derive(Copy, Clone)]
+pub enum Relation {
+    /// Equal, '=='.
+    Eq,
+    /// Less than or equal, '<='.
+    Le,
+    /// Greater than or equal, '>='.
+    Ge,
+}
+
 // Pretty-printers.
 
 impl<'a> Print for Document<'a> {
@@ -107,6 +125,7 @@ impl<'a> Print for Stmt<'a> {
             Stmt::Return(ref r) => f.print(r),
             Stmt::Block(ref bk) => f.print(bk),
             Stmt::PutAt(ref pa) => f.print(pa),
+            Stmt::Constraint(ref c) => f.print(c),
         }
     }
 }
@@ -143,15 +162,33 @@ impl<'a> Print for Term<'a> {
         match *self {
             // TODO: Should escape strings.
             Term::String(ref st) => f.print(&st[..]),
+            Term::PrefixedString(prefix, ref st) => {
+                f.print(prefix);
+                f.print(&st[..]);
+            }
             Term::Number(ref nm) => f.print(nm),
             Term::Color(ref col) => f.print(col),
             Term::Idents(ref is) => f.print(is),
             Term::Coord(ref coo) => f.print(coo),
+            Term::List(ref elems) => {
+                f.print("[");
+                let mut first = true;
+                for elem in elems {
+                    if !first { f.print(", "); }
+                    f.print(elem);
+                    first = false;
+                }
+                f.print("]");
+            }
             Term::BinOp(ref bop) => f.print(bop),
             Term::UnOp(ref unop) => f.print(unop),
             Term::FnCall(ref fc) => f.print(fc),
             Term::FnDef(ref fdf) => f.print(fdf),
             Term::Block(ref blk) => f.print(blk),
+            Term::Layout(ref blk) => {
+                f.print("layout");
+                f.print(blk);
+            }
         }
     }
 }
@@ -294,3 +331,23 @@ impl<'a> Print for PutAt<'a> {
         f.print(&self.1);
     }
 }
+
+impl<'a> Print for Constraint<'a> {
+    fn print(&self, f: &mut Formatter) {
+        f.print(&self.0);
+        f.print(" ");
+        f.print(&self.1);
+        f.print(" ");
+        f.print(&self.2);
+    }
+}
+
+impl Print for Relation {
+    fn print(&self, f: &mut Formatter) {
+        match *self {
+            Relation::Eq => f.print("=="),
+            Relation::Le => f.print("<="),
+            Relation::Ge => f.print(">="),
+        }
+    }
+}
@@ -32,18 +32,32 @@ const USAGE: &'static str = "
 Pris, a language for designing slides.
 
 Usage:
-  pris [--output=<outfile>] [--] <infile>
+  pris [options] [--font-dir=<dir>...] [--] <infile>
   pris (-h | --help)
 
 Options:
   -h --help              Show this screen.
   -o --output <outfile>  Write to the specified file, instead of infile.pdf.
+  --font-dir <dir>       Load the fonts in the directory. They take priority
+                         over installed fonts. Can be repeated.
+  --font-substitution <mode>
+                         What to do when a font family is not installed and a
+                         different family would be substituted: 'allow',
+                         'warn', or 'error'. [default: warn]
+  --list-fonts           Print the font files that text was drawn in.
+  --report               Print the fonts embedded in the output, with their
+                         glyph counts and embedding permissions. Exit with
+                         status 1 if a font does not permit its embedding.
 ";
 
 #[derive(Debug, RustcDecodable)]
 struct Args {
     arg_infile: String,
     flag_output: Option<String>,
+    flag_font_dir: Vec<String>,
+    flag_font_substitution: String,
+    flag_list_fonts: bool,
+    flag_report: bool,
 }
 
 fn main() {
@@ -82,6 +96,23 @@ fn main() {
     println!("Evaluating document ...");
 
     let mut fm = runtime::FontMap::new();
+    fm.set_substitution(match &args.flag_font_substitution[..] {
+        "allow" => runtime::Substitution::Allow,
+        "warn" => runtime::Substitution::Warn,
+        "error" => runtime::Substitution::Error,
+        other => {
+            let msg = format!("Invalid --font-substitution '{}', expected 'allow', 'warn', or 'error'.", other);
+            Error::Other(msg).print();
+            std::process::exit(1)
+        }
+    });
+    for dir in &args.flag_font_dir {
+        if !fm.add_font_dir(Path::new(dir)) {
+            Error::Other(format!("Failed to load fonts from '{}'.", dir)).print();
+            std::process::exit(1)
+        }
+    }
+
     let mut frames = Vec::new();
     let mut context_frame = runtime::Frame::new();
     for statement in &doc.0 {
@@ -105,6 +136,48 @@ fn main() {
     drop(cr);
 
     println!("Document written to {}.", outfile.to_str().unwrap());
+
+    if args.flag_list_fonts {
+        println!("Fonts used:");
+        for font in fm.used_fonts() {
+            println!("  {} {}: {}", font.family, font.style, font.file.description());
+        }
+    }
+
+    if args.flag_report {
+        let num_violations = print_font_report(&mut fm);
+        if num_violations > 0 {
+            std::process::exit(1)
+        }
+    }
+}
+
+/// Print the fonts that are embedded in the output document. Returns the
+/// number of fonts whose embedding permissions are violated.
+fn print_font_report(fm: &mut runtime::FontMap) -> usize {
+    let fonts = fm.used_fonts();
+    let mut num_violations = 0;
+
+    println!("Embedded fonts:");
+    for font in &fonts {
+        println!("  {} {}", font.family, font.style);
+        println!("    file:      {}", font.file.description());
+        println!("    glyphs:    {}", font.glyph_count);
+        match font.embedding {
+            Some(embedding) => {
+                println!("    embedding: {}", embedding.description());
+                if let Some(violation) = embedding.violation() {
+                    println!("    violation: {}", violation);
+                    num_violations += 1;
+                }
+            }
+            None => println!("    embedding: unknown, the font has no OS/2 table"),
+        }
+    }
+
+    println!("{} fonts embedded, {} of which violate their embedding permissions.",
+             fonts.len(), num_violations);
+    num_violations
 }
 
 fn report_error(input: &[u8], location: usize, len: usize) {
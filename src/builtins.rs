@@ -5,18 +5,28 @@
 // it under the terms of the GNU General Public License version 3. A copy
 // of the License is available in the root of the repository.
 
+use std::iter;
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
+use std::usize;
 
 use ast::Idents;
 use cairo;
-use elements::{Element, FillPolygon, StrokePolygon, Text, Vec2};
+use cairo::Matrix;
+use elements::{Color, Element, Fill, GlyphRun, Gradient, Paint, Shape, Stroke, StrokePolygon, Text, Vec2};
 use error::{Error, Result};
+use fontconfig::FontFile;
 use freetype;
 use harfbuzz;
+use hyphenation::Patterns;
 use pretty::Formatter;
 use rsvg;
-use runtime::{BoundingBox, Env, FontMap, Frame, Val};
+use runtime::{BoundingBox, Env, FontMap, FontSpec, Frame, Slant, Span, SpanStyle, Val};
+use runtime::weight_from_name;
+use sfnt;
 use types::ValType;
+use unicode_bidi::{BidiInfo, Level};
+use xi_unicode::LineBreakIterator;
 
 fn validate_args<'a>(fn_name: &str,
                      expected: &[ValType],
@@ -81,17 +91,123 @@ pub fn fit<'a>(_fm: &mut FontMap,
         return Err(Error::Other("Cannot fit a frame of size (0w, 0w).".into()))
     };
 
+    let scaled_frame = transform_frame(&frame, Matrix::scale(scale, scale));
+    Ok(Val::Frame(Rc::new(scaled_frame)))
+}
+
+/// Wraps the elements of a frame in a transform about the frame's origin.
+///
+/// The anchor and bounding box of the new frame are transformed as well.
+fn transform_frame<'a>(frame: &Frame<'a>, matrix: Matrix) -> Frame<'a> {
     // As the frame is immutable anyway, it would actually be possible to refer
     // to the elements in the frame, instead of copying them. If performance
     // ever becomes a concern, this would be a good place to start.
     let elements: Vec<_> = frame.get_elements().iter().cloned().collect();
+    let anchor = frame.get_anchor();
+    let (ax, ay) = matrix.transform_point(anchor.x, anchor.y);
 
-    let mut scaled_frame = Frame::from_env(frame.get_env().clone());
-    scaled_frame.place_element(Vec2::zero(), Element::Scaled(elements, scale));
-    scaled_frame.set_anchor(frame.get_anchor() * scale);
-    scaled_frame.union_bounding_box(&frame.get_bounding_box().scale(scale));
+    let mut transformed_frame = Frame::from_env(frame.get_env().clone());
+    transformed_frame.place_element(Vec2::zero(), Element::Transformed(elements, matrix));
+    transformed_frame.set_anchor(Vec2::new(ax, ay));
+    transformed_frame.union_boxes(&frame.get_bounding_box().transform(&matrix),
+                                  &frame.get_ink_box().transform(&matrix));
+    transformed_frame
+}
 
-    Ok(Val::Frame(Rc::new(scaled_frame)))
+/// Returns a copy of the frame with its contents and anchor moved by the offset.
+fn translate_frame<'a>(frame: &Frame<'a>, offset: Vec2) -> Frame<'a> {
+    let mut translated_frame = Frame::from_env(frame.get_env().clone());
+    translated_frame.place_frame(offset, frame);
+    translated_frame.set_anchor(frame.get_anchor() + offset);
+    translated_frame
+}
+
+/// Rotates a frame clockwise around its origin, by an angle in degrees.
+pub fn rotate<'a>(_fm: &mut FontMap,
+                  _env: &Env<'a>,
+                  mut args: Vec<Val<'a>>)
+                  -> Result<Val<'a>> {
+    validate_args("rotate", &[ValType::Frame, ValType::Num(0)], &args)?;
+    let frame = match args.remove(0) {
+        Val::Frame(f) => f,
+        _ => unreachable!(),
+    };
+    let degrees = match args.remove(0) {
+        Val::Num(x, 0) => x,
+        _ => unreachable!(),
+    };
+
+    let matrix = Matrix::rotate(degrees.to_radians());
+    Ok(Val::Frame(Rc::new(transform_frame(&frame, matrix))))
+}
+
+/// Scales a frame by separate horizontal and vertical factors.
+pub fn scale<'a>(_fm: &mut FontMap,
+                 _env: &Env<'a>,
+                 mut args: Vec<Val<'a>>)
+                 -> Result<Val<'a>> {
+    validate_args("scale", &[ValType::Frame, ValType::Coord(0)], &args)?;
+    let frame = match args.remove(0) {
+        Val::Frame(f) => f,
+        _ => unreachable!(),
+    };
+    let (sx, sy) = match args.remove(0) {
+        Val::Coord(x, y, 0) => (x, y),
+        _ => unreachable!(),
+    };
+
+    let matrix = Matrix::scale(sx, sy);
+    Ok(Val::Frame(Rc::new(transform_frame(&frame, matrix))))
+}
+
+/// Skews a frame by a horizontal and vertical angle in degrees.
+///
+/// A horizontal skew shifts points to the right proportional to their y
+/// coordinate, a vertical skew shifts points down proportional to x.
+pub fn skew<'a>(_fm: &mut FontMap,
+                _env: &Env<'a>,
+                mut args: Vec<Val<'a>>)
+                -> Result<Val<'a>> {
+    validate_args("skew", &[ValType::Frame, ValType::Coord(0)], &args)?;
+    let frame = match args.remove(0) {
+        Val::Frame(f) => f,
+        _ => unreachable!(),
+    };
+    let (ax, ay) = match args.remove(0) {
+        Val::Coord(x, y, 0) => (x, y),
+        _ => unreachable!(),
+    };
+
+    let matrix = Matrix::new(1.0, ay.to_radians().tan(), ax.to_radians().tan(), 1.0, 0.0, 0.0);
+    Ok(Val::Frame(Rc::new(transform_frame(&frame, matrix))))
+}
+
+/// Mirrors a frame in its origin, either "horizontal" (left becomes right) or
+/// "vertical" (top becomes bottom).
+pub fn mirror<'a>(_fm: &mut FontMap,
+                  _env: &Env<'a>,
+                  mut args: Vec<Val<'a>>)
+                  -> Result<Val<'a>> {
+    validate_args("mirror", &[ValType::Frame, ValType::Str], &args)?;
+    let frame = match args.remove(0) {
+        Val::Frame(f) => f,
+        _ => unreachable!(),
+    };
+    let matrix = match args.remove(0) {
+        Val::Str(ref s) if s == "horizontal" => Matrix::scale(-1.0, 1.0),
+        Val::Str(ref s) if s == "vertical" => Matrix::scale(1.0, -1.0),
+        Val::Str(other) => {
+            let mut fmt = Formatter::new();
+            fmt.print("'");
+            fmt.print(&other[..]);
+            fmt.print("' is not a valid direction for 'mirror'. ");
+            fmt.print("Must be one of 'horizontal', 'vertical'.");
+            return Err(Error::value(fmt.into_string()))
+        }
+        _ => unreachable!(),
+    };
+
+    Ok(Val::Frame(Rc::new(transform_frame(&frame, matrix))))
 }
 
 pub fn line<'a>(_fm: &mut FontMap,
@@ -106,7 +222,7 @@ pub fn line<'a>(_fm: &mut FontMap,
 
     let line = StrokePolygon {
         // TODO: Better idents type for non-ast use?
-        color: env.lookup_color(&Idents(vec!["color"]))?,
+        paint: env.lookup_paint(&Idents(vec!["color"]))?,
         line_width: env.lookup_len(&Idents(vec!["line_width"]))?,
         close: false,
         vertices: vec![Vec2::zero(), offset],
@@ -131,19 +247,19 @@ pub fn fill_rectangle<'a>(_fm: &mut FontMap,
         _ => unreachable!(),
     };
 
-    let rect = FillPolygon {
+    let rect = Fill {
         // TODO: Better idents type for non-ast use?
-        color: env.lookup_color(&Idents(vec!["color"]))?,
-        vertices: vec![
+        paint: env.lookup_paint(&Idents(vec!["color"]))?,
+        shape: Shape::Polygon(vec![
             Vec2::zero(),
             Vec2::new(0.0, h),
             Vec2::new(w, h),
             Vec2::new(w, 0.0),
-        ],
+        ]),
     };
 
     let mut frame = Frame::new();
-    frame.place_element(Vec2::zero(), Element::FillPolygon(rect));
+    frame.place_element(Vec2::zero(), Element::Fill(rect));
     frame.set_anchor(Vec2::new(w, h));
     // TODO: Make bounding box take Vec2.
     frame.union_bounding_box(&BoundingBox::sized(w, h));
@@ -151,170 +267,2750 @@ pub fn fill_rectangle<'a>(_fm: &mut FontMap,
     Ok(Val::Frame(Rc::new(frame)))
 }
 
-pub fn str<'a>(_fm: &mut FontMap,
-               _env: &Env<'a>,
-               mut args: Vec<Val<'a>>)
-               -> Result<Val<'a>> {
-    // TODO: Make this generic over the dimension?
-    validate_args("str", &[ValType::Num(0)], &args)?;
-    let num = match args.remove(0) {
-        Val::Num(x, _) => x,
+/// Extracts the colors from a list of gradient stops.
+fn gradient_stops<'a>(fn_name: &str, stops: Vec<Val<'a>>) -> Result<Vec<Color>> {
+    if stops.len() < 2 {
+        let msg = format!("'{}' needs at least two color stops, but {} {} given.",
+                          fn_name, stops.len(), if stops.len() == 1 { "was" } else { "were" });
+        return Err(Error::value(msg))
+    }
+
+    let mut colors = Vec::with_capacity(stops.len());
+    for stop in stops {
+        match stop {
+            Val::Col(col) => colors.push(col),
+            other => {
+                let mut fmt = Formatter::new();
+                fmt.print("Expected color stops of '");
+                fmt.print(fn_name);
+                fmt.print("' to be colors, but found '");
+                fmt.print(other.get_type());
+                fmt.print("'.");
+                return Err(Error::value(fmt.into_string()))
+            }
+        }
+    }
+
+    Ok(colors)
+}
+
+pub fn linear_gradient<'a>(_fm: &mut FontMap,
+                           _env: &Env<'a>,
+                           mut args: Vec<Val<'a>>)
+                           -> Result<Val<'a>> {
+    validate_args("linear_gradient", &[ValType::Coord(1), ValType::Coord(1), ValType::List], &args)?;
+    let from = match args.remove(0) {
+        Val::Coord(x, y, 1) => Vec2::new(x, y),
+        _ => unreachable!(),
+    };
+    let to = match args.remove(0) {
+        Val::Coord(x, y, 1) => Vec2::new(x, y),
+        _ => unreachable!(),
+    };
+    let stops = match args.remove(0) {
+        Val::List(stops) => gradient_stops("linear_gradient", stops)?,
         _ => unreachable!(),
     };
 
-    Ok(Val::Str(format!("{}", num)))
+    let gradient = Gradient::Linear {
+        from: from,
+        to: to,
+        stops: stops,
+    };
+
+    Ok(Val::Gradient(gradient))
 }
 
-/// Typesets a single line of text.
-///
-/// Returns the glyphs as well as the width of the line.
-fn typeset_line(ft_face: &mut freetype::Face<'static>,
-                font_size: f64,
-                text: &str)
-                -> (Vec<cairo::Glyph>, f64) {
-    // Shape the text using Harfbuzz: convert the UTF-8 string and input font
-    // into a list of glyphs with offsets.
-    let mut hb_font = harfbuzz::Font::from_ft_face(ft_face);
+pub fn radial_gradient<'a>(_fm: &mut FontMap,
+                           _env: &Env<'a>,
+                           mut args: Vec<Val<'a>>)
+                           -> Result<Val<'a>> {
+    validate_args("radial_gradient", &[ValType::Coord(1), ValType::Num(1), ValType::List], &args)?;
+    let center = match args.remove(0) {
+        Val::Coord(x, y, 1) => Vec2::new(x, y),
+        _ => unreachable!(),
+    };
+    let radius = match args.remove(0) {
+        Val::Num(r, 1) => r,
+        _ => unreachable!(),
+    };
+    let stops = match args.remove(0) {
+        Val::List(stops) => gradient_stops("radial_gradient", stops)?,
+        _ => unreachable!(),
+    };
+
+    let gradient = Gradient::Radial {
+        center: center,
+        radius: radius,
+        stops: stops,
+    };
+
+    Ok(Val::Gradient(gradient))
+}
+
+/// Fills a circle with the given radius. The origin of the frame is the top
+/// left corner of the square that contains the circle.
+pub fn fill_circle<'a>(_fm: &mut FontMap,
+                       env: &Env<'a>,
+                       mut args: Vec<Val<'a>>)
+                       -> Result<Val<'a>> {
+    validate_args("fill_circle", &[ValType::Num(1)], &args)?;
+    let r = match args.remove(0) {
+        Val::Num(r, 1) => r,
+        _ => unreachable!(),
+    };
+
+    let circle = Fill {
+        paint: env.lookup_paint(&Idents(vec!["color"]))?,
+        shape: Shape::Circle {
+            center: Vec2::new(r, r),
+            radius: r,
+        },
+    };
+
+    let mut frame = Frame::new();
+    frame.place_element(Vec2::zero(), Element::Fill(circle));
+    frame.set_anchor(Vec2::new(2.0 * r, 2.0 * r));
+    frame.union_bounding_box(&BoundingBox::sized(2.0 * r, 2.0 * r));
+
+    Ok(Val::Frame(Rc::new(frame)))
+}
 
-    let mut hb_buffer = harfbuzz::Buffer::new(harfbuzz::Direction::LeftToRight);
-    hb_buffer.add_str(&text);
-    hb_buffer.shape(&mut hb_font);
+/// Fills the polygon with the given list of vertices. The anchor of the frame
+/// is the last vertex.
+pub fn fill_polygon<'a>(_fm: &mut FontMap,
+                        env: &Env<'a>,
+                        mut args: Vec<Val<'a>>)
+                        -> Result<Val<'a>> {
+    validate_args("fill_polygon", &[ValType::List], &args)?;
+    let vals = match args.remove(0) {
+        Val::List(vals) => vals,
+        _ => unreachable!(),
+    };
 
-    // Position all the glyphs: Harfbuzz gives offsets, but we need absolute
-    // locations. Store them in the representation that Cairo expects.
-    let hb_glyphs = hb_buffer.glyphs();
-    let mut cr_glyphs = Vec::with_capacity(hb_glyphs.len());
-    let (mut cur_x, mut cur_y) = (0.0, 0.0);
+    if vals.len() < 3 {
+        let msg = format!("'fill_polygon' needs at least three vertices, but {} {} given.",
+                          vals.len(), if vals.len() == 1 { "was" } else { "were" });
+        return Err(Error::value(msg))
+    }
 
-    // Compensate for the fixed font size which is set for the Freetype font,
-    // and apply the desired font size.
-    let size_factor = font_size / 1000.0;
+    let mut vertices = Vec::with_capacity(vals.len());
+    for val in vals {
+        match val {
+            Val::Coord(x, y, 1) => vertices.push(Vec2::new(x, y)),
+            other => {
+                let mut fmt = Formatter::new();
+                fmt.print("Expected vertices of 'fill_polygon' to have type '");
+                fmt.print(ValType::Coord(1));
+                fmt.print("', but found '");
+                fmt.print(other.get_type());
+                fmt.print("'.");
+                return Err(Error::value(fmt.into_string()))
+            }
+        }
+    }
 
-    for hg in hb_glyphs {
-        cur_x += hg.x_offset as f64 * size_factor;
-        cur_y += hg.y_offset as f64 * size_factor;
-        let cg = cairo::Glyph::new(hg.codepoint as u64, cur_x, cur_y);
-        cur_x += hg.x_advance as f64 * size_factor;
-        cur_y += hg.y_advance as f64 * size_factor;
-        cr_glyphs.push(cg);
+    let mut bb = BoundingBox::new(vertices[0], Vec2::zero());
+    for &v in &vertices[1..] {
+        bb = bb.union(&BoundingBox::new(v, Vec2::zero()));
     }
+    let anchor = vertices[vertices.len() - 1];
+
+    let polygon = Fill {
+        paint: env.lookup_paint(&Idents(vec!["color"]))?,
+        shape: Shape::Polygon(vertices),
+    };
+
+    let mut frame = Frame::new();
+    frame.place_element(Vec2::zero(), Element::Fill(polygon));
+    frame.set_anchor(anchor);
+    frame.union_bounding_box(&bb);
 
-    (cr_glyphs, cur_x)
+    Ok(Val::Frame(Rc::new(frame)))
 }
 
-/// Split a string on newlines.
-///
-/// Unlike `std::str::lines`, the final newline is not swallowed.
-fn split_lines(text: &str) -> Vec<&str> {
-    // TODO: This might return an iterator instead of a vector.
-    // At this point it is not worth the trouble performance-wise.
-    let mut lines = Vec::new();
-    let mut left = text;
-    while let Some(index) = left.find("\n") {
-        lines.push(&left[0..index]);
-        left = &left[index + 1..];
+/// Fills a rectangle with rounded corners, with its top left corner at the
+/// origin.
+pub fn rounded_rectangle<'a>(_fm: &mut FontMap,
+                             env: &Env<'a>,
+                             mut args: Vec<Val<'a>>)
+                             -> Result<Val<'a>> {
+    validate_args("rounded_rectangle", &[ValType::Coord(1), ValType::Num(1)], &args)?;
+    let (w, h) = match args.remove(0) {
+        Val::Coord(x, y, 1) => (x, y),
+        _ => unreachable!(),
+    };
+    let radius = match args.remove(0) {
+        Val::Num(r, 1) => r,
+        _ => unreachable!(),
+    };
+    check_nonnegative("rounded_rectangle", "width", w)?;
+    check_nonnegative("rounded_rectangle", "height", h)?;
+    check_nonnegative("rounded_rectangle", "radius", radius)?;
+
+    let rect = Fill {
+        paint: env.lookup_paint(&Idents(vec!["color"]))?,
+        shape: Shape::RoundedRectangle {
+            size: Vec2::new(w, h),
+            radius: radius,
+        },
+    };
+
+    let mut frame = Frame::new();
+    frame.place_element(Vec2::zero(), Element::Fill(rect));
+    frame.set_anchor(Vec2::new(w, h));
+    frame.union_bounding_box(&BoundingBox::sized(w, h));
+
+    Ok(Val::Frame(Rc::new(frame)))
+}
+
+/// Converts a color or gradient argument into a paint.
+fn paint_arg<'a>(fn_name: &str, val: Val<'a>, arg_num: u32) -> Result<Paint> {
+    match val {
+        Val::Col(col) => Ok(Paint::Solid(col)),
+        Val::Gradient(grad) => Ok(Paint::Gradient(grad)),
+        other => Err(Error::arg_type(fn_name, ValType::Color, other.get_type(), arg_num)),
     }
-    lines.push(left);
-    lines
 }
 
-#[test]
-fn split_lines_returns_as_many_lines_as_newlines_plus_one() {
-    let text = "\nfoo\nbar\n";
-    let lines = split_lines(text);
-    assert_eq!(&lines, &["", "foo", "bar", ""]);
+/// Returns an error if a length argument is negative.
+fn check_nonnegative(fn_name: &str, what: &str, value: f64) -> Result<()> {
+    if value < 0.0 {
+        let msg = format!("The {} passed to '{}' must not be negative, but {} was given.",
+                          what, fn_name, value);
+        return Err(Error::value(msg))
+    }
+    Ok(())
 }
 
-pub fn t<'a>(fm: &mut FontMap,
-             env: &Env<'a>,
-             mut args: Vec<Val<'a>>)
-             -> Result<Val<'a>> {
-    validate_args("t", &[ValType::Str], &args)?;
-    let text = match args.remove(0) {
-        Val::Str(s) => s,
+/// Moves a frame such that a point of its bounding box is at the origin. The
+/// point is given as a fraction of the size, so (0.5, 1.0) is the middle of the
+/// bottom edge.
+pub fn align<'a>(_fm: &mut FontMap,
+                 _env: &Env<'a>,
+                 mut args: Vec<Val<'a>>)
+                 -> Result<Val<'a>> {
+    validate_args("align", &[ValType::Frame, ValType::Coord(0)], &args)?;
+    let frame = match args.remove(0) {
+        Val::Frame(f) => f,
+        _ => unreachable!(),
+    };
+    let (fx, fy) = match args.remove(0) {
+        Val::Coord(x, y, 0) => (x, y),
+        _ => unreachable!(),
+    };
+    let bb = frame.get_bounding_box();
+    let offset = Vec2::new(-bb.x - fx * bb.width, -bb.y - fy * bb.height);
+    Ok(Val::Frame(Rc::new(translate_frame(&frame, offset))))
+}
+
+/// Moves a frame such that the center of its bounding box is at the origin.
+pub fn center<'a>(_fm: &mut FontMap,
+                  _env: &Env<'a>,
+                  args: Vec<Val<'a>>)
+                  -> Result<Val<'a>> {
+    validate_args("center", &[ValType::Frame], &args)?;
+    let frame = match args[0] {
+        Val::Frame(ref f) => f,
         _ => unreachable!(),
     };
-    let text_lines = split_lines(&text);
+    let bb = frame.get_bounding_box();
+    let offset = Vec2::new(-bb.x - 0.5 * bb.width, -bb.y - 0.5 * bb.height);
+    Ok(Val::Frame(Rc::new(translate_frame(frame, offset))))
+}
 
-    enum TextAlign { Left, Center, Right }
+/// Returns a copy of a frame with a different anchor. The anchor is where the
+/// next frame goes when adjoining frames with '~'.
+pub fn with_anchor<'a>(_fm: &mut FontMap,
+                       _env: &Env<'a>,
+                       mut args: Vec<Val<'a>>)
+                       -> Result<Val<'a>> {
+    validate_args("with_anchor", &[ValType::Frame, ValType::Coord(1)], &args)?;
+    let frame = match args.remove(0) {
+        Val::Frame(f) => f,
+        _ => unreachable!(),
+    };
+    let anchor = match args.remove(0) {
+        Val::Coord(x, y, 1) => Vec2::new(x, y),
+        _ => unreachable!(),
+    };
+    let mut anchored_frame = (*frame).clone();
+    anchored_frame.set_anchor(anchor);
+    Ok(Val::Frame(Rc::new(anchored_frame)))
+}
 
-    // Read the font details from the 'font_family' and 'font_style' variables,
-    // and locate the corresponding FreeType face. The line height is a bit of a
-    // problem; we could make it dimensionless and relative to the font size --
-    // which would make it scale automatically -- but then specifying absolute
-    // line heights would be a bit of a hassle. We could make it absolute, but
-    // then it does not scale automatically. Or we could allow both here:
-    // numbers have units, so we could figure out what to do. But my gut feeling
-    // is that dynamic typing will be confusing in the end.
-    let font_family = env.lookup_str(&Idents(vec!["font_family"]))?;
-    let font_style = env.lookup_str(&Idents(vec!["font_style"]))?;
-    let font_size = env.lookup_len(&Idents(vec!["font_size"]))?;
-    let line_height = env.lookup_len(&Idents(vec!["line_height"]))?;
-    let text_align = env.lookup_str(&Idents(vec!["text_align"]))?;
-    let mut ft_face = match fm.get(&font_family, &font_style) {
-        Some(face) => face,
-        None => return Err(Error::missing_font(font_family, font_style)),
+/// Returns the offset that moves a box such that the given edge lines up with
+/// the same edge of the reference box.
+fn align_to_offset(bb: &BoundingBox, reference: &BoundingBox, edge: &str) -> Option<Vec2> {
+    let dx_left = reference.x - bb.x;
+    let dx_right = (reference.x + reference.width) - (bb.x + bb.width);
+    let dy_top = reference.y - bb.y;
+    let dy_bottom = (reference.y + reference.height) - (bb.y + bb.height);
+    let dx_center = 0.5 * (dx_left + dx_right);
+    let dy_center = 0.5 * (dy_top + dy_bottom);
+    match edge {
+        "left" => Some(Vec2::new(dx_left, 0.0)),
+        "right" => Some(Vec2::new(dx_right, 0.0)),
+        "top" => Some(Vec2::new(0.0, dy_top)),
+        "bottom" => Some(Vec2::new(0.0, dy_bottom)),
+        "hcenter" => Some(Vec2::new(dx_center, 0.0)),
+        "vcenter" => Some(Vec2::new(0.0, dy_center)),
+        "center" => Some(Vec2::new(dx_center, dy_center)),
+        _ => None,
+    }
+}
+
+#[test]
+fn align_to_offset_lines_up_edges() {
+    let bb = BoundingBox::new(Vec2::new(1.0, 1.0), Vec2::new(2.0, 2.0));
+    let reference = BoundingBox::sized(6.0, 4.0);
+    let offset = |edge| {
+        let v = align_to_offset(&bb, &reference, edge).unwrap();
+        (v.x, v.y)
     };
-    let ta = match text_align.as_ref() {
-        "left" => TextAlign::Left,
-        "center" => TextAlign::Center,
-        "right" => TextAlign::Right,
-        other => {
-            // TODO: Move this error to an error at assignment time, not at
-            // evaluation time. More type safety is more better.
+    assert_eq!(offset("left"), (-1.0, 0.0));
+    assert_eq!(offset("right"), (3.0, 0.0));
+    assert_eq!(offset("bottom"), (0.0, 1.0));
+    assert_eq!(offset("center"), (1.0, 0.0));
+    assert!(align_to_offset(&bb, &reference, "middle").is_none());
+}
+
+/// Moves a frame such that an edge of its bounding box lines up with the same
+/// edge of a reference frame, when both are placed at the same position. The
+/// edge is one of "left", "right", "top", "bottom", "hcenter", "vcenter", or
+/// "center" to center in both directions.
+pub fn align_to<'a>(_fm: &mut FontMap,
+                    _env: &Env<'a>,
+                    mut args: Vec<Val<'a>>)
+                    -> Result<Val<'a>> {
+    validate_args("align_to", &[ValType::Frame, ValType::Frame, ValType::Str], &args)?;
+    let frame = match args.remove(0) {
+        Val::Frame(f) => f,
+        _ => unreachable!(),
+    };
+    let reference = match args.remove(0) {
+        Val::Frame(f) => f,
+        _ => unreachable!(),
+    };
+    let edge = match args.remove(0) {
+        Val::Str(s) => s,
+        _ => unreachable!(),
+    };
+    match align_to_offset(frame.get_bounding_box(), reference.get_bounding_box(), &edge) {
+        Some(offset) => Ok(Val::Frame(Rc::new(translate_frame(&frame, offset)))),
+        None => {
             let mut fmt = Formatter::new();
             fmt.print("'");
-            fmt.print(other);
-            fmt.print("' is not a valid value for 'text_align'. ");
-            fmt.print("Must be one of 'left', 'center', 'right'.");
-            return Err(Error::value(fmt.into_string()))
+            fmt.print(&edge[..]);
+            fmt.print("' is not a valid edge for 'align_to'. Must be one of ");
+            fmt.print("'left', 'right', 'top', 'bottom', 'hcenter', 'vcenter', 'center'.");
+            Err(Error::value(fmt.into_string()))
         }
+    }
+}
+
+/// Extends the bounding box of a frame by a margin on every side. The margin
+/// is a length, or a coordinate with the horizontal and vertical margin. The
+/// contents, ink box and anchor stay in place.
+pub fn pad<'a>(_fm: &mut FontMap,
+               _env: &Env<'a>,
+               mut args: Vec<Val<'a>>)
+               -> Result<Val<'a>> {
+    if args.len() != 2 {
+        return Err(Error::arity("pad", 2, args.len() as u32))
+    }
+    let frame = match args.remove(0) {
+        Val::Frame(f) => f,
+        other => return Err(Error::arg_type("pad", ValType::Frame, other.get_type(), 0)),
+    };
+    let margin = match args.remove(0) {
+        Val::Num(m, 1) => Vec2::new(m, m),
+        Val::Coord(mx, my, 1) => Vec2::new(mx, my),
+        other => return Err(Error::arg_type("pad", ValType::Num(1), other.get_type(), 1)),
     };
 
-    let mut glyphs = Vec::new();
-    let mut max_width: f64 = 0.0;
-    let mut min_offset: f64 = 0.0;
-    let mut cur_x = 0.0;
-    let mut cur_y = 0.0;
-    for line in text_lines {
-        let (line_glyphs, width) = typeset_line(ft_face, font_size, line);
+    let mut padded_frame = translate_frame(&frame, Vec2::zero());
+    let bb = frame.get_bounding_box();
+    let padded_box = BoundingBox::new(
+        Vec2::new(bb.x - margin.x, bb.y - margin.y),
+        Vec2::new(bb.width + 2.0 * margin.x, bb.height + 2.0 * margin.y),
+    );
+    padded_frame.union_boxes(&padded_box, frame.get_ink_box());
+    Ok(Val::Frame(Rc::new(padded_frame)))
+}
 
-        // Apply x offset to enforce text alignment.
-        let offset = match ta {
-            TextAlign::Left => 0.0,
-            TextAlign::Center => width * -0.5,
-            TextAlign::Right => width * -1.0,
+/// Wraps a frame in a (rounded) box, with padding around the bounding box of
+/// the frame. A border of nonzero width is drawn in the current color.
+pub fn boxed<'a>(_fm: &mut FontMap,
+                 env: &Env<'a>,
+                 mut args: Vec<Val<'a>>)
+                 -> Result<Val<'a>> {
+    if args.len() != 5 {
+        return Err(Error::arity("box", 5, args.len() as u32))
+    }
+    let frame = match args.remove(0) {
+        Val::Frame(f) => f,
+        other => return Err(Error::arg_type("box", ValType::Frame, other.get_type(), 0)),
+    };
+    let padding = match args.remove(0) {
+        Val::Num(p, 1) => p,
+        other => return Err(Error::arg_type("box", ValType::Num(1), other.get_type(), 1)),
+    };
+    let border = match args.remove(0) {
+        Val::Num(b, 1) => b,
+        other => return Err(Error::arg_type("box", ValType::Num(1), other.get_type(), 2)),
+    };
+    let fill_paint = paint_arg("box", args.remove(0), 3)?;
+    let radius = match args.remove(0) {
+        Val::Num(r, 1) => r,
+        other => return Err(Error::arg_type("box", ValType::Num(1), other.get_type(), 4)),
+    };
+    check_nonnegative("box", "padding", padding)?;
+    check_nonnegative("box", "border width", border)?;
+    check_nonnegative("box", "radius", radius)?;
+
+    // The box surrounds the bounding box of the contents, not the origin, so
+    // contents that extend into negative coordinates (such as text above its
+    // baseline) are enclosed too.
+    let bb = frame.get_bounding_box();
+    let box_pos = Vec2::new(bb.x - padding, bb.y - padding);
+    let box_size = Vec2::new(bb.width + 2.0 * padding, bb.height + 2.0 * padding);
+    let shape = Shape::RoundedRectangle {
+        size: box_size,
+        radius: radius,
+    };
+
+    let mut boxed_frame = Frame::from_env(frame.get_env().clone());
+
+    let background = Fill {
+        paint: fill_paint,
+        shape: shape.clone(),
+    };
+    boxed_frame.place_element(box_pos, Element::Fill(background));
+
+    // Half of the border lies outside of the box.
+    let mut outer = BoundingBox::new(box_pos, box_size);
+    if border > 0.0 {
+        let outline = Stroke {
+            paint: env.lookup_paint(&Idents(vec!["color"]))?,
+            line_width: border,
+            shape: shape,
         };
+        boxed_frame.place_element(box_pos, Element::Stroke(outline));
+        let half = 0.5 * border;
+        outer = BoundingBox::new(box_pos + Vec2::new(-half, -half),
+                                 box_size + Vec2::new(border, border));
+    }
 
-        for g in line_glyphs {
-            glyphs.push(g.offset(offset, cur_y));
-        }
+    for pe in frame.get_elements() {
+        boxed_frame.place_element(pe.position, pe.element.clone());
+    }
+    boxed_frame.set_anchor(frame.get_anchor());
+    boxed_frame.union_boxes(frame.get_bounding_box(), frame.get_ink_box());
+    boxed_frame.union_bounding_box(&outer);
 
-        max_width = max_width.max(width);
-        min_offset = min_offset.min(offset);
-        cur_y += line_height;
-        cur_x = offset + width;
+    Ok(Val::Frame(Rc::new(boxed_frame)))
+}
+
+/// Crops a frame to a rectangle of the given size at its origin, or to the
+/// filled shapes in a second frame.
+pub fn clip<'a>(_fm: &mut FontMap,
+                _env: &Env<'a>,
+                mut args: Vec<Val<'a>>)
+                -> Result<Val<'a>> {
+    if args.len() != 2 {
+        return Err(Error::arity("clip", 2, args.len() as u32))
     }
+    let frame = match args.remove(0) {
+        Val::Frame(f) => f,
+        other => return Err(Error::arg_type("clip", ValType::Frame, other.get_type(), 0)),
+    };
 
-    let text_elem = Text {
-        color: env.lookup_color(&Idents(vec!["color"]))?,
-        font_family: font_family,
-        font_style: font_style,
-        font_size: font_size,
-        glyphs: glyphs,
+    let (clip_shapes, clip_bb) = match args.remove(0) {
+        Val::Coord(w, h, 1) => {
+            let rect = Shape::Polygon(vec![
+                Vec2::zero(),
+                Vec2::new(0.0, h),
+                Vec2::new(w, h),
+                Vec2::new(w, 0.0),
+            ]);
+            (vec![(Vec2::zero(), rect)], BoundingBox::sized(w, h))
+        }
+        Val::Frame(shape_frame) => {
+            let mut shapes = Vec::with_capacity(shape_frame.get_elements().len());
+            for pe in shape_frame.get_elements() {
+                match pe.element {
+                    Element::Fill(ref fill) => shapes.push((pe.position, fill.shape.clone())),
+                    _ => {
+                        let msg = "Can only clip to filled shapes, such as those made by \
+                                   'fill_rectangle' or 'fill_circle'.";
+                        return Err(Error::value(msg.into()))
+                    }
+                }
+            }
+            (shapes, shape_frame.get_bounding_box().clone())
+        }
+        other => return Err(Error::arg_type("clip", ValType::Frame, other.get_type(), 1)),
     };
 
-    let mut frame = Frame::new();
-    frame.place_element(Vec2::zero(), Element::Text(text_elem));
-    frame.set_anchor(Vec2::new(cur_x, cur_y - line_height));
+    let elements: Vec<_> = frame.get_elements().iter().cloned().collect();
 
-    let top_left = Vec2::new(min_offset, -line_height);
-    let size = Vec2::new(max_width, cur_y);
-    frame.union_bounding_box(&BoundingBox::new(top_left, size));
+    let mut clipped_frame = Frame::from_env(frame.get_env().clone());
+    clipped_frame.place_element(Vec2::zero(), Element::Clipped(elements, clip_shapes));
+    clipped_frame.set_anchor(frame.get_anchor());
+    clipped_frame.union_boxes(&frame.get_bounding_box().intersect(&clip_bb),
+                              &frame.get_ink_box().intersect(&clip_bb));
 
+    Ok(Val::Frame(Rc::new(clipped_frame)))
+}
+
+/// Extracts the frames from a list argument.
+fn frames_arg<'a>(fn_name: &str, vals: Vec<Val<'a>>) -> Result<Vec<Rc<Frame<'a>>>> {
+    let mut frames = Vec::with_capacity(vals.len());
+    for val in vals {
+        match val {
+            Val::Frame(frame) => frames.push(frame),
+            other => {
+                let mut fmt = Formatter::new();
+                fmt.print("Expected the elements of the list passed to '");
+                fmt.print(fn_name);
+                fmt.print("' to have type '");
+                fmt.print(ValType::Frame);
+                fmt.print("', but found '");
+                fmt.print(other.get_type());
+                fmt.print("'.");
+                return Err(Error::value(fmt.into_string()))
+            }
+        }
+    }
+    Ok(frames)
+}
+
+/// How frames are aligned in the direction perpendicular to a stack.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum Align {
+    Start,
+    Center,
+    End,
+    /// Align the origins of the frames, so for text, the baselines.
+    Baseline,
+}
+
+/// Read an alignment variable, such as 'stack_align'.
+fn lookup_align<'a>(env: &Env<'a>, var_name: &'static str) -> Result<Align> {
+    parse_align(var_name, &env.lookup_str(&Idents(vec![var_name]))?)
+}
+
+/// Parse the value of an alignment variable.
+fn parse_align(var_name: &str, value: &str) -> Result<Align> {
+    match value {
+        "start" => Ok(Align::Start),
+        "center" => Ok(Align::Center),
+        "end" => Ok(Align::End),
+        "baseline" => Ok(Align::Baseline),
+        other => {
+            let mut fmt = Formatter::new();
+            fmt.print("'");
+            fmt.print(other);
+            fmt.print("' is not a valid value for '");
+            fmt.print(var_name);
+            fmt.print("'. Must be one of 'start', 'center', 'end', 'baseline'.");
+            Err(Error::value(fmt.into_string()))
+        }
+    }
+}
+
+impl Align {
+    /// Returns the offset that aligns an extent, given as start and length,
+    /// in a space of the given length that starts at zero.
+    fn offset(&self, start: f64, length: f64, space: f64) -> f64 {
+        match *self {
+            Align::Start => -start,
+            Align::Center => (space - length) * 0.5 - start,
+            Align::End => space - length - start,
+            Align::Baseline => 0.0,
+        }
+    }
+}
+
+/// Places frames one after another, separated by spacing, and aligned across
+/// the stacking direction according to 'stack_align'. The stack starts at the
+/// origin, and its anchor is the anchor of the last frame.
+fn stack<'a>(frames: &[Rc<Frame<'a>>], spacing: f64, align: Align, is_vertical: bool) -> Frame<'a> {
+    // Express the boxes as (main start, main length, cross start, cross
+    // length), where the main axis is the stacking direction.
+    let extents = |bb: &BoundingBox| if is_vertical {
+        (bb.y, bb.height, bb.x, bb.width)
+    } else {
+        (bb.x, bb.width, bb.y, bb.height)
+    };
+    let cross_space = frames.iter()
+        .map(|f| extents(f.get_bounding_box()).3)
+        .fold(0.0, f64::max);
+
+    let mut result = Frame::new();
+    let mut cursor = 0.0;
+    for frame in frames {
+        let (main_start, main_length, cross_start, cross_length) = extents(frame.get_bounding_box());
+        let main = cursor - main_start;
+        let cross = align.offset(cross_start, cross_length, cross_space);
+        let position = if is_vertical { Vec2::new(cross, main) } else { Vec2::new(main, cross) };
+        result.place_frame(position, frame);
+        result.set_anchor(position + frame.get_anchor());
+        cursor += main_length + spacing;
+    }
+    result
+}
+
+#[test]
+fn stack_aligns_frames_across_the_stacking_direction() {
+    let mut small = Frame::new();
+    small.union_bounding_box(&BoundingBox::new(Vec2::new(-1.0, -1.0), Vec2::new(2.0, 2.0)));
+    let mut large = Frame::new();
+    large.union_bounding_box(&BoundingBox::sized(4.0, 4.0));
+    let frames = vec![Rc::new(small), Rc::new(large)];
+
+    let result = stack(&frames, 1.0, Align::Center, true);
+    let bb = result.get_bounding_box();
+    assert_eq!((bb.x, bb.y, bb.width, bb.height), (0.0, 0.0, 4.0, 7.0));
+
+    let result = stack(&frames, 1.0, Align::Baseline, false);
+    let bb = result.get_bounding_box();
+    assert_eq!((bb.x, bb.y, bb.width, bb.height), (0.0, -1.0, 7.0, 5.0));
+}
+
+/// Stacks a list of frames vertically, from top to bottom.
+pub fn vstack<'a>(_fm: &mut FontMap,
+                  env: &Env<'a>,
+                  mut args: Vec<Val<'a>>)
+                  -> Result<Val<'a>> {
+    validate_args("vstack", &[ValType::List, ValType::Num(1)], &args)?;
+    let frames = match args.remove(0) {
+        Val::List(vals) => frames_arg("vstack", vals)?,
+        _ => unreachable!(),
+    };
+    let spacing = match args.remove(0) {
+        Val::Num(s, 1) => s,
+        _ => unreachable!(),
+    };
+    let align = lookup_align(env, "stack_align")?;
+    Ok(Val::Frame(Rc::new(stack(&frames, spacing, align, true))))
+}
+
+/// Stacks a list of frames horizontally, from left to right.
+pub fn hstack<'a>(_fm: &mut FontMap,
+                  env: &Env<'a>,
+                  mut args: Vec<Val<'a>>)
+                  -> Result<Val<'a>> {
+    validate_args("hstack", &[ValType::List, ValType::Num(1)], &args)?;
+    let frames = match args.remove(0) {
+        Val::List(vals) => frames_arg("hstack", vals)?,
+        _ => unreachable!(),
+    };
+    let spacing = match args.remove(0) {
+        Val::Num(s, 1) => s,
+        _ => unreachable!(),
+    };
+    let align = lookup_align(env, "stack_align")?;
+    Ok(Val::Frame(Rc::new(stack(&frames, spacing, align, false))))
+}
+
+/// Read 'cell_align' or 'cell_valign', which is an alignment for all cells,
+/// or a list with an alignment for every cell.
+fn lookup_cell_align<'a>(env: &Env<'a>, var_name: &'static str, num_cells: usize) -> Result<Vec<Align>> {
+    match env.lookup(&Idents(vec![var_name]))? {
+        Val::Str(s) => Ok(vec![parse_align(var_name, &s)?; num_cells]),
+        Val::List(vals) => {
+            if vals.len() != num_cells {
+                let mut fmt = Formatter::new();
+                fmt.print("'");
+                fmt.print(var_name);
+                fmt.print("' has ");
+                fmt.print(vals.len());
+                fmt.print(" alignments, but the grid has ");
+                fmt.print(num_cells);
+                fmt.print(" cells.");
+                return Err(Error::value(fmt.into_string()))
+            }
+            let mut aligns = Vec::with_capacity(vals.len());
+            for val in vals {
+                match val {
+                    Val::Str(s) => aligns.push(parse_align(var_name, &s)?),
+                    other => {
+                        let mut fmt = Formatter::new();
+                        fmt.print("Expected the elements of '");
+                        fmt.print(var_name);
+                        fmt.print("' to have type '");
+                        fmt.print(ValType::Str);
+                        fmt.print("', but found '");
+                        fmt.print(other.get_type());
+                        fmt.print("'.");
+                        return Err(Error::value(fmt.into_string()))
+                    }
+                }
+            }
+            Ok(aligns)
+        }
+        other => Err(Error::var_type(&Idents(vec![var_name]), ValType::Str, other.get_type())),
+    }
+}
+
+/// Read 'grid_row_height', which is "auto" or a length for all rows, or a
+/// list of those for the first rows. Returns the height of every row, where
+/// `None` means that the row fits its highest cell.
+fn lookup_row_heights<'a>(env: &Env<'a>, num_rows: usize) -> Result<Vec<Option<f64>>> {
+    let row_height = |val: Val<'a>| match val {
+        Val::Str(ref s) if s == "auto" => Ok(None),
+        Val::Num(h, 1) => Ok(Some(h)),
+        _ => {
+            let msg = "'grid_row_height' must be 'auto', a length, or a list of those.";
+            Err(Error::value(msg.to_string()))
+        }
+    };
+    match env.lookup(&Idents(vec!["grid_row_height"]))? {
+        Val::List(vals) => {
+            let mut heights = Vec::with_capacity(num_rows);
+            for val in vals.into_iter().take(num_rows) {
+                heights.push(row_height(val)?);
+            }
+            while heights.len() < num_rows {
+                heights.push(None);
+            }
+            Ok(heights)
+        }
+        val => Ok(vec![row_height(val)?; num_rows]),
+    }
+}
+
+/// Returns the size of a row or column of a grid that fits all of its cells,
+/// given as (start, length, alignment) along the track. Cells with baseline
+/// alignment have their origins line up, so for those the parts before and
+/// after the origin add up.
+fn track_size(extents: &[(f64, f64, Align)]) -> f64 {
+    let origin = track_origin(extents);
+    extents.iter()
+        .map(|&(start, length, align)| match align {
+            Align::Baseline => origin + start + length,
+            _ => length,
+        })
+        .fold(0.0, f64::max)
+}
+
+/// Returns the offset of the origin from the start of a track, for the cells
+/// with baseline alignment.
+fn track_origin(extents: &[(f64, f64, Align)]) -> f64 {
+    extents.iter()
+        .filter(|&&(_, _, align)| align == Align::Baseline)
+        .map(|&(start, _, _)| -start)
+        .fold(0.0, f64::max)
+}
+
+/// Places frames in a grid with the given number of columns, row by row.
+/// Tracks without a fixed size fit their largest cell. Every cell has its own
+/// horizontal and vertical alignment. The grid starts at the origin, its
+/// bounding box includes all cells, and its anchor is the anchor of the last
+/// frame.
+fn lay_out_grid<'a>(frames: &[Rc<Frame<'a>>],
+                    column_widths: &[Option<f64>],
+                    row_heights: &[Option<f64>],
+                    gap: Vec2,
+                    aligns: &[Align],
+                    valigns: &[Align])
+                    -> Frame<'a> {
+    let num_columns = column_widths.len();
+    let num_rows = (frames.len() + num_columns - 1) / num_columns;
+
+    let column_extents: Vec<Vec<(f64, f64, Align)>> = (0..num_columns).map(|c| {
+        frames.iter().zip(aligns).enumerate()
+            .filter(|&(i, _)| i % num_columns == c)
+            .map(|(_, (f, &align))| (f.get_bounding_box().x, f.get_bounding_box().width, align))
+            .collect()
+    }).collect();
+    let row_extents: Vec<Vec<(f64, f64, Align)>> = (0..num_rows).map(|r| {
+        frames.iter().zip(valigns).enumerate()
+            .filter(|&(i, _)| i / num_columns == r)
+            .map(|(_, (f, &valign))| (f.get_bounding_box().y, f.get_bounding_box().height, valign))
+            .collect()
+    }).collect();
+
+    let widths: Vec<f64> = column_widths.iter().zip(&column_extents)
+        .map(|(w, extents)| w.unwrap_or_else(|| track_size(extents)))
+        .collect();
+    let heights: Vec<f64> = row_extents.iter().enumerate()
+        .map(|(r, extents)| row_heights.get(r).cloned().unwrap_or(None)
+            .unwrap_or_else(|| track_size(extents)))
+        .collect();
+
+    // The start of every track, followed by the end of the grid.
+    let starts = |sizes: &[f64], gap: f64| {
+        let mut starts = vec![0.0];
+        for size in sizes {
+            let last = starts[starts.len() - 1];
+            starts.push(last + size + gap);
+        }
+        starts
+    };
+    let xs = starts(&widths, gap.x);
+    let ys = starts(&heights, gap.y);
+
+    let offset = |align: Align, extents: &[(f64, f64, Align)], start: f64, length: f64, space: f64| {
+        match align {
+            Align::Baseline => track_origin(extents),
+            _ => align.offset(start, length, space),
+        }
+    };
+
+    let mut result = Frame::new();
+    for (i, frame) in frames.iter().enumerate() {
+        let (column, row) = (i % num_columns, i / num_columns);
+        let bb = frame.get_bounding_box();
+        let x = xs[column] + offset(aligns[i], &column_extents[column], bb.x, bb.width, widths[column]);
+        let y = ys[row] + offset(valigns[i], &row_extents[row], bb.y, bb.height, heights[row]);
+        let position = Vec2::new(x, y);
+        result.place_frame(position, frame);
+        result.set_anchor(position + frame.get_anchor());
+    }
+
+    if num_rows > 0 {
+        let size = Vec2::new(xs[num_columns] - gap.x, ys[num_rows] - gap.y);
+        result.union_bounding_box(&BoundingBox::new(Vec2::new(0.0, 0.0), size));
+    }
+
+    result
+}
+
+#[test]
+fn lay_out_grid_sizes_tracks_by_their_largest_cell() {
+    let sized = |w, h| {
+        let mut frame = Frame::new();
+        frame.union_bounding_box(&BoundingBox::sized(w, h));
+        Rc::new(frame)
+    };
+    let frames = vec![sized(1.0, 1.0), sized(3.0, 1.0), sized(2.0, 2.0)];
+    let gap = Vec2::new(1.0, 0.5);
+
+    let center = [Align::Center; 3];
+    let start = [Align::Start; 3];
+    let result = lay_out_grid(&frames, &[None, None], &[], gap, &center, &start);
+    let bb = result.get_bounding_box();
+    assert_eq!((bb.x, bb.y, bb.width, bb.height), (0.0, 0.0, 6.0, 3.5));
+    let anchor = result.get_anchor();
+    assert_eq!((anchor.x, anchor.y), (0.0, 1.5));
+
+    let end = [Align::End; 3];
+    let result = lay_out_grid(&frames, &[Some(4.0), None], &[Some(2.0), Some(2.0)], gap, &end, &start);
+    let bb = result.get_bounding_box();
+    assert_eq!((bb.x, bb.y, bb.width, bb.height), (0.0, 0.0, 8.0, 4.5));
+    let anchor = result.get_anchor();
+    assert_eq!((anchor.x, anchor.y), (2.0, 2.5));
+
+    // Rows without a height fit their cells, and cells align on their own.
+    let aligns = [Align::End, Align::End, Align::Start];
+    let result = lay_out_grid(&frames, &[Some(4.0), None], &[Some(2.0)], gap, &aligns, &start);
+    let bb = result.get_bounding_box();
+    assert_eq!((bb.x, bb.y, bb.width, bb.height), (0.0, 0.0, 8.0, 4.5));
+    let anchor = result.get_anchor();
+    assert_eq!((anchor.x, anchor.y), (0.0, 2.5));
+}
+
+/// Places a list of frames in a grid, row by row. The column argument is
+/// either the number of columns, which then fit their widest frame, or a
+/// list of column widths. The gap is a length, or a coordinate with the gap
+/// between columns and the gap between rows. Cells are aligned according to
+/// 'cell_align' and 'cell_valign', and rows are sized by 'grid_row_height'.
+pub fn grid<'a>(_fm: &mut FontMap,
+                env: &Env<'a>,
+                mut args: Vec<Val<'a>>)
+                -> Result<Val<'a>> {
+    if args.len() != 3 {
+        return Err(Error::arity("grid", 3, args.len() as u32))
+    }
+    let frames = match args.remove(0) {
+        Val::List(vals) => frames_arg("grid", vals)?,
+        other => return Err(Error::arg_type("grid", ValType::List, other.get_type(), 0)),
+    };
+    let column_widths = match args.remove(0) {
+        // Columns past the last frame would be empty, so do not allocate
+        // them, however many are asked for.
+        Val::Num(n, 0) if n >= 1.0 && n.fract() == 0.0 => {
+            vec![None; (n as usize).min(frames.len()).max(1)]
+        }
+        Val::Num(_, 0) => {
+            let msg = "The number of columns passed to 'grid' must be a positive integer.";
+            return Err(Error::value(msg.to_string()))
+        }
+        Val::List(vals) => {
+            let mut widths = Vec::with_capacity(vals.len());
+            for val in vals {
+                match val {
+                    Val::Num(w, 1) => widths.push(Some(w)),
+                    other => {
+                        let mut fmt = Formatter::new();
+                        fmt.print("Expected column widths of 'grid' to have type '");
+                        fmt.print(ValType::Num(1));
+                        fmt.print("', but found '");
+                        fmt.print(other.get_type());
+                        fmt.print("'.");
+                        return Err(Error::value(fmt.into_string()))
+                    }
+                }
+            }
+            if widths.is_empty() {
+                let msg = "The list of column widths passed to 'grid' must not be empty.";
+                return Err(Error::value(msg.to_string()))
+            }
+            widths
+        }
+        other => return Err(Error::arg_type("grid", ValType::Num(0), other.get_type(), 1)),
+    };
+    let gap = match args.remove(0) {
+        Val::Num(g, 1) => Vec2::new(g, g),
+        Val::Coord(x, y, 1) => Vec2::new(x, y),
+        other => return Err(Error::arg_type("grid", ValType::Num(1), other.get_type(), 2)),
+    };
+    let num_rows = (frames.len() + column_widths.len() - 1) / column_widths.len();
+    let aligns = lookup_cell_align(env, "cell_align", frames.len())?;
+    let valigns = lookup_cell_align(env, "cell_valign", frames.len())?;
+    let row_heights = lookup_row_heights(env, num_rows)?;
+    let frame = lay_out_grid(&frames, &column_widths, &row_heights, gap, &aligns, &valigns);
+    Ok(Val::Frame(Rc::new(frame)))
+}
+
+pub fn str<'a>(_fm: &mut FontMap,
+               _env: &Env<'a>,
+               mut args: Vec<Val<'a>>)
+               -> Result<Val<'a>> {
+    // TODO: Make this generic over the dimension?
+    validate_args("str", &[ValType::Num(0)], &args)?;
+    let num = match args.remove(0) {
+        Val::Num(x, _) => x,
+        _ => unreachable!(),
+    };
+
+    Ok(Val::Str(format!("{}", num)))
+}
+
+/// A single line of shaped text.
+struct Line {
+    runs: Vec<GlyphRun>,
+
+    /// The advance width of the line.
+    width: f64,
+
+    /// The extents of the glyph outlines, relative to the start of the
+    /// baseline. This is `None` if the line contains no ink, e.g. when it is
+    /// empty or consists only of spaces.
+    ink: Option<BoundingBox>,
+
+    /// The locations of space glyphs as (run, glyph) indices, in visual order.
+    /// Justification widens these.
+    spaces: Vec<(usize, usize)>,
+
+    /// For every run, the start and end of its advance on the baseline, and
+    /// the decorations to draw along it.
+    extents: Vec<(f64, f64, Decorations)>,
+}
+
+/// Lines and backgrounds that are drawn along with text. Offsets and
+/// thicknesses are absolute lengths, with offsets relative to the baseline,
+/// positive downwards.
+#[derive(Clone)]
+struct Decorations {
+    /// The offset of the top of the underline, and its thickness.
+    underline: Option<(f64, f64)>,
+
+    /// The offset of the top of the strikethrough line, and its thickness.
+    strikethrough: Option<(f64, f64)>,
+
+    /// The paint for the background of the text, and the offsets of its top
+    /// and bottom, which are the ascender and descender of the font.
+    highlight: Option<(Paint, f64, f64)>,
+}
+
+/// The font, paint, and shaping settings that text is typeset with, read from
+/// the environment and the style of a span.
+struct TextStyle {
+    font: FontSpec,
+
+    /// The font file for the font spec.
+    font_file: FontFile,
+
+    /// Design coordinates for variable fonts, derived from the weight,
+    /// stretch, and slant, and 'font_variations'.
+    variations: Vec<harfbuzz::Variation>,
+
+    font_size: f64,
+    paint: Paint,
+    shaping: Shaping,
+    decorations: Decorations,
+}
+
+/// Settings that control how text is shaped, read from the environment.
+struct Shaping {
+    features: Vec<harfbuzz::Feature>,
+
+    /// The paragraph direction for the bidirectional algorithm. It is detected
+    /// from the text if this is `None`.
+    base_level: Option<Level>,
+
+    /// The script to shape with. It is detected per run if this is `None`.
+    script: Option<harfbuzz::Script>,
+
+    /// The language to shape with. Harfbuzz picks a default if this is `None`.
+    language: Option<harfbuzz::Language>,
+}
+
+/// Returns the extents of the outline of a glyph placed at (x, y).
+///
+/// The `units_factor` converts from font units to the desired font size.
+fn glyph_ink_box(ft_face: &freetype::Face<'static>,
+                 glyph_index: u32,
+                 x: f64,
+                 y: f64,
+                 units_factor: f64)
+                 -> Option<BoundingBox> {
+    // Load the glyph unscaled, so the metrics are in font units, independent
+    // of the size set on the face.
+    if ft_face.load_glyph(glyph_index, freetype::face::NO_SCALE).is_err() {
+        return None
+    }
+
+    let metrics = ft_face.glyph().metrics();
+    if metrics.width == 0 || metrics.height == 0 {
+        return None
+    }
+
+    // The y-axis of font units points up, ours points down.
+    let top_left = Vec2::new(x + metrics.horiBearingX as f64 * units_factor,
+                             y - metrics.horiBearingY as f64 * units_factor);
+    let size = Vec2::new(metrics.width as f64 * units_factor,
+                         metrics.height as f64 * units_factor);
+    Some(BoundingBox::new(top_left, size))
+}
+
+impl Line {
+    fn new() -> Line {
+        Line {
+            runs: Vec::new(),
+            width: 0.0,
+            ink: None,
+            spaces: Vec::new(),
+            extents: Vec::new(),
+        }
+    }
+
+    /// Append glyphs shaped by Harfbuzz from `text` at the end of the line, as
+    /// a new run.
+    fn append(&mut self,
+              style: &TextStyle,
+              font_file: &FontFile,
+              ft_face: &freetype::Face<'static>,
+              text: &str,
+              hb_glyphs: &[harfbuzz::Glyph]) {
+        self.runs.push(GlyphRun {
+            paint: style.paint.clone(),
+            font_file: font_file.clone(),
+            variations: style.variations.clone(),
+            font_size: style.font_size,
+            glyphs: Vec::new(),
+        });
+        let run_index = self.runs.len() - 1;
+        let run_start = self.width;
+        let glyphs = &mut self.runs[run_index].glyphs;
+        let font_size = style.font_size;
+
+        // Compensate for the fixed font size which is set for the Freetype
+        // font, and apply the desired font size.
+        let size_factor = font_size / 1000.0;
+
+        // Glyph metrics are read in font units, which need a different factor.
+        let units_factor = font_size / ft_face.em_size() as f64;
+
+        // Position all the glyphs: Harfbuzz gives advances and offsets, but we
+        // need absolute locations. Offsets do not move the pen, and their
+        // y-axis points up. Store the glyphs in the representation that Cairo
+        // expects. For horizontal text the vertical advance is zero, so the pen
+        // returns to the baseline at the end of every run.
+        let mut pen_y = 0.0;
+        for hg in hb_glyphs {
+            if text[hg.cluster as usize..].starts_with(' ') {
+                self.spaces.push((run_index, glyphs.len()));
+            }
+
+            let x = self.width + hg.x_offset as f64 * size_factor;
+            let y = pen_y - hg.y_offset as f64 * size_factor;
+            glyphs.push(cairo::Glyph::new(hg.codepoint as u64, x, y));
+            if let Some(glyph_ink) = glyph_ink_box(ft_face, hg.codepoint, x, y, units_factor) {
+                self.ink = Some(match self.ink {
+                    Some(ref bb) => bb.union(&glyph_ink),
+                    None => glyph_ink,
+                });
+            }
+            self.width += hg.x_advance as f64 * size_factor;
+            pen_y -= hg.y_advance as f64 * size_factor;
+        }
+
+        self.extents.push((run_start, self.width, style.decorations.clone()));
+    }
+
+    /// Widen the spaces in the line so the line becomes `width` wide. Lines
+    /// without spaces, or lines that are too wide already, are left as is.
+    fn justify(&mut self, width: f64) {
+        if self.spaces.len() == 0 || width <= self.width {
+            return
+        }
+
+        // Every glyph moves right by the extra space of the spaces before it.
+        let extra = (width - self.width) / self.spaces.len() as f64;
+        let mut shift = 0.0;
+        let mut spaces = self.spaces.iter().peekable();
+        for (i, run) in self.runs.iter_mut().enumerate() {
+            self.extents[i].0 += shift;
+            for (j, glyph) in run.glyphs.iter_mut().enumerate() {
+                *glyph = glyph.offset(shift, 0.0);
+                if spaces.peek() == Some(&&(i, j)) {
+                    spaces.next();
+                    shift += extra;
+                }
+            }
+            self.extents[i].1 += shift;
+        }
+
+        // The line does not end in a space, so the ink reaches further by
+        // the full amount.
+        if let Some(ref mut ink) = self.ink {
+            ink.width += width - self.width;
+        }
+
+        self.width = width;
+    }
+}
+
+#[test]
+fn justify_widens_decorations_with_their_spaces() {
+    let decorations = Decorations { underline: None, strikethrough: None, highlight: None };
+    let run = |x: f64| GlyphRun {
+        paint: Paint::Solid(Color::new(0.0, 0.0, 0.0)),
+        font_file: FontFile::new(PathBuf::new()),
+        variations: Vec::new(),
+        font_size: 1.0,
+        glyphs: vec![cairo::Glyph::new(0, x, 0.0), cairo::Glyph::new(0, x + 1.0, 0.0)],
+    };
+    // Two runs of two glyphs, where the second glyph of the first run is a
+    // space.
+    let mut line = Line::new();
+    line.runs = vec![run(0.0), run(2.0)];
+    line.width = 4.0;
+    line.spaces = vec![(0, 1)];
+    line.extents = vec![(0.0, 2.0, decorations.clone()), (2.0, 4.0, decorations)];
+    line.justify(6.0);
+    assert_eq!((line.extents[0].0, line.extents[0].1), (0.0, 4.0));
+    assert_eq!((line.extents[1].0, line.extents[1].1), (4.0, 6.0));
+}
+
+/// Parses a comma-separated list of OpenType features, such as
+/// "smcp, onum, -liga".
+fn parse_font_features(features: &str) -> Result<Vec<harfbuzz::Feature>> {
+    let mut result = Vec::new();
+    for feature_str in features.split(',').map(|f| f.trim()).filter(|f| f.len() > 0) {
+        match harfbuzz::Feature::parse(feature_str) {
+            Some(feature) => result.push(feature),
+            None => {
+                let mut fmt = Formatter::new();
+                fmt.print("'");
+                fmt.print(feature_str);
+                fmt.print("' in 'font_features' is not a valid OpenType feature. ");
+                fmt.print("Expected a tag such as 'smcp', optionally prefixed ");
+                fmt.print("with '-' to disable it.");
+                return Err(Error::value(fmt.into_string()))
+            }
+        }
+    }
+    Ok(result)
+}
+
+impl Shaping {
+    /// Read the 'font_features', 'text_direction', 'text_script', and
+    /// 'text_language' variables.
+    fn from_env<'a>(env: &Env<'a>) -> Result<Shaping> {
+        let font_features = env.lookup_str(&Idents(vec!["font_features"]))?;
+        let text_direction = env.lookup_str(&Idents(vec!["text_direction"]))?;
+        let text_script = env.lookup_str(&Idents(vec!["text_script"]))?;
+        let text_language = env.lookup_str(&Idents(vec!["text_language"]))?;
+
+        let base_level = match text_direction.as_ref() {
+            "auto" => None,
+            "ltr" => Some(Level::ltr()),
+            "rtl" => Some(Level::rtl()),
+            other => {
+                let mut fmt = Formatter::new();
+                fmt.print("'");
+                fmt.print(other);
+                fmt.print("' is not a valid value for 'text_direction'. ");
+                fmt.print("Must be one of 'auto', 'ltr', 'rtl'.");
+                return Err(Error::value(fmt.into_string()))
+            }
+        };
+
+        let script = match text_script.as_ref() {
+            "auto" => None,
+            tag => match harfbuzz::Script::parse(tag) {
+                Some(script) => Some(script),
+                None => {
+                    let mut fmt = Formatter::new();
+                    fmt.print("'");
+                    fmt.print(tag);
+                    fmt.print("' is not a valid value for 'text_script'. ");
+                    fmt.print("Must be 'auto' or an ISO 15924 tag such as 'Latn'.");
+                    return Err(Error::value(fmt.into_string()))
+                }
+            },
+        };
+
+        let language = match text_language.as_ref() {
+            "auto" => None,
+            tag => match harfbuzz::Language::parse(tag) {
+                Some(language) => Some(language),
+                None => {
+                    let mut fmt = Formatter::new();
+                    fmt.print("'");
+                    fmt.print(tag);
+                    fmt.print("' is not a valid value for 'text_language'. ");
+                    fmt.print("Must be 'auto' or a language tag such as 'en'.");
+                    return Err(Error::value(fmt.into_string()))
+                }
+            },
+        };
+
+        let shaping = Shaping {
+            features: parse_font_features(&font_features)?,
+            base_level: base_level,
+            script: script,
+            language: language,
+        };
+        Ok(shaping)
+    }
+}
+
+/// Returns the font style with bold and italic added, e.g. "Bold Italic" for
+/// "Italic" made bold. Words that denote the regular style, such as "roman",
+/// are dropped from the result.
+fn emphasize_style(style: &str, bold: bool, italic: bool) -> String {
+    if !bold && !italic {
+        return style.to_string()
+    }
+
+    let is_any = |word: &str, names: &[&str]| names.contains(&&word.to_lowercase()[..]);
+    let mut words: Vec<&str> = style
+        .split_whitespace()
+        .filter(|w| !is_any(w, &["roman", "regular", "normal", "book"]))
+        .collect();
+
+    if bold && !words.iter().any(|w| is_any(w, &["bold"])) {
+        words.insert(0, "Bold");
+    }
+    if italic && !words.iter().any(|w| is_any(w, &["italic", "oblique"])) {
+        words.push("Italic");
+    }
+
+    words.join(" ")
+}
+
+#[test]
+fn emphasize_style_combines_bold_and_italic() {
+    assert_eq!(emphasize_style("roman", false, false), "roman");
+    assert_eq!(emphasize_style("roman", true, false), "Bold");
+    assert_eq!(emphasize_style("Italic", true, false), "Bold Italic");
+    assert_eq!(emphasize_style("Bold", true, true), "Bold Italic");
+}
+
+impl TextStyle {
+    /// Read the 'font_family', 'font_style', 'font_size', and 'color'
+    /// variables, the font selection and shaping variables, apply the
+    /// overrides of the span style, and locate the font file.
+    fn from_env<'a>(fm: &mut FontMap, env: &Env<'a>, span_style: &SpanStyle) -> Result<TextStyle> {
+        let font_family = if span_style.code {
+            env.lookup_str(&Idents(vec!["code_font_family"]))?
+        } else {
+            env.lookup_str(&Idents(vec!["font_family"]))?
+        };
+        let font_style = emphasize_style(&env.lookup_str(&Idents(vec!["font_style"]))?,
+                                         span_style.bold,
+                                         span_style.italic);
+        let font_size = match span_style.font_size {
+            Some(size) => size,
+            None => env.lookup_len(&Idents(vec!["font_size"]))?,
+        };
+        let paint = match span_style.color {
+            Some(ref paint) => paint.clone(),
+            None => env.lookup_paint(&Idents(vec!["color"]))?,
+        };
+        let mut shaping = Shaping::from_env(env)?;
+        for features in &span_style.font_features {
+            shaping.features.extend(parse_font_features(features)?);
+        }
+
+        // Bold and italic spans are at least bold and italic, also when the
+        // weight and slant are set explicitly.
+        let mut weight = lookup_font_weight(env)?;
+        if span_style.bold {
+            weight = weight.map(|w| w.max(700));
+        }
+        let mut slant = lookup_font_slant(env)?;
+        if span_style.italic && slant == Some(Slant::Roman) {
+            slant = Some(Slant::Italic);
+        }
+        let font = FontSpec {
+            family: font_family,
+            style: font_style,
+            weight: weight,
+            stretch: lookup_font_stretch(env)?,
+            slant: slant,
+        };
+
+        // Variable fonts do not have a file per weight or width, so set the
+        // design axes too. Fonts ignore the axes they do not have.
+        let mut variations = Vec::new();
+        if let Some(weight) = font.weight() {
+            variations.push(harfbuzz::Variation::new(b"wght", weight as f32));
+        }
+        if let Some(stretch) = font.stretch {
+            variations.push(harfbuzz::Variation::new(b"wdth", stretch as f32));
+        }
+        match font.slant() {
+            Some(Slant::Roman) => variations.push(harfbuzz::Variation::new(b"ital", 0.0)),
+            Some(Slant::Italic) => variations.push(harfbuzz::Variation::new(b"ital", 1.0)),
+            // The default oblique angle in CSS is 14 degrees. The 'slnt' axis
+            // counts counter-clockwise, so the angle is negative.
+            Some(Slant::Oblique) => variations.push(harfbuzz::Variation::new(b"slnt", -14.0)),
+            None => {}
+        }
+        let font_variations = env.lookup_str(&Idents(vec!["font_variations"]))?;
+        variations.extend(parse_font_variations(&font_variations)?);
+
+        let font_file = match env.lookup_str(&Idents(vec!["font_file"]))?.as_ref() {
+            "auto" => fm.locate(&font)?,
+            path => {
+                if !Path::new(path).is_file() {
+                    return Err(Error::missing_file(path.to_string()))
+                }
+                let font_file = FontFile::new(PathBuf::from(path));
+                fm.load(&font_file)?;
+                font_file
+            }
+        };
+        let decorations = Decorations::from_env(env, fm.get_instance(&font_file, &variations), font_size)?;
+        let style = TextStyle {
+            font: font,
+            font_file: font_file,
+            variations: variations,
+            font_size: font_size,
+            paint: paint,
+            shaping: shaping,
+            decorations: decorations,
+        };
+        Ok(style)
+    }
+}
+
+/// Read the 'font_weight' variable, which is "auto", a weight name such as
+/// "semibold", or a number between 1 and 1000.
+fn lookup_font_weight<'a>(env: &Env<'a>) -> Result<Option<i32>> {
+    match env.lookup(&Idents(vec!["font_weight"]))? {
+        Val::Str(ref s) if s == "auto" => Ok(None),
+        Val::Str(ref s) if weight_from_name(s).is_some() => Ok(weight_from_name(s)),
+        Val::Num(w, 0) if w >= 1.0 && w <= 1000.0 => Ok(Some(w.round() as i32)),
+        _ => {
+            let mut fmt = Formatter::new();
+            fmt.print("'font_weight' must be 'auto', a number between 1 and ");
+            fmt.print("1000, or one of 'thin', 'extralight', 'light', 'regular', ");
+            fmt.print("'medium', 'semibold', 'bold', 'extrabold', 'black'.");
+            Err(Error::value(fmt.into_string()))
+        }
+    }
+}
+
+/// Read the 'font_stretch' variable, which is "auto", a width name such as
+/// "condensed", or a percentage of the normal width.
+fn lookup_font_stretch<'a>(env: &Env<'a>) -> Result<Option<i32>> {
+    // These are the names and values that Fontconfig uses.
+    let stretch = match env.lookup(&Idents(vec!["font_stretch"]))? {
+        Val::Str(ref s) => match s.as_ref() {
+            "auto" => return Ok(None),
+            "ultracondensed" => Some(50),
+            "extracondensed" => Some(63),
+            "condensed" => Some(75),
+            "semicondensed" => Some(87),
+            "normal" => Some(100),
+            "semiexpanded" => Some(113),
+            "expanded" => Some(125),
+            "extraexpanded" => Some(150),
+            "ultraexpanded" => Some(200),
+            _ => None,
+        },
+        Val::Num(w, 0) if w > 0.0 && w <= 1000.0 => Some(w.round() as i32),
+        _ => None,
+    };
+    match stretch {
+        Some(..) => Ok(stretch),
+        None => {
+            let mut fmt = Formatter::new();
+            fmt.print("'font_stretch' must be 'auto', a percentage of the normal ");
+            fmt.print("width, or one of 'ultracondensed', 'extracondensed', ");
+            fmt.print("'condensed', 'semicondensed', 'normal', 'semiexpanded', ");
+            fmt.print("'expanded', 'extraexpanded', 'ultraexpanded'.");
+            Err(Error::value(fmt.into_string()))
+        }
+    }
+}
+
+/// Read the 'font_slant' variable.
+fn lookup_font_slant<'a>(env: &Env<'a>) -> Result<Option<Slant>> {
+    match env.lookup_str(&Idents(vec!["font_slant"]))?.as_ref() {
+        "auto" => Ok(None),
+        "roman" => Ok(Some(Slant::Roman)),
+        "italic" => Ok(Some(Slant::Italic)),
+        "oblique" => Ok(Some(Slant::Oblique)),
+        other => {
+            let mut fmt = Formatter::new();
+            fmt.print("'");
+            fmt.print(other);
+            fmt.print("' is not a valid value for 'font_slant'. ");
+            fmt.print("Must be one of 'auto', 'roman', 'italic', 'oblique'.");
+            Err(Error::value(fmt.into_string()))
+        }
+    }
+}
+
+/// Parses a comma-separated list of variable font axis settings, such as
+/// "wght=650, wdth=80".
+fn parse_font_variations(variations: &str) -> Result<Vec<harfbuzz::Variation>> {
+    let mut result = Vec::new();
+    for variation_str in variations.split(',').map(|v| v.trim()).filter(|v| v.len() > 0) {
+        match harfbuzz::Variation::parse(variation_str) {
+            Some(variation) => result.push(variation),
+            None => {
+                let mut fmt = Formatter::new();
+                fmt.print("'");
+                fmt.print(variation_str);
+                fmt.print("' in 'font_variations' is not a valid axis setting. ");
+                fmt.print("Expected a tag and a value, such as 'wght=650'.");
+                return Err(Error::value(fmt.into_string()))
+            }
+        }
+    }
+    Ok(result)
+}
+
+/// Read a decoration line variable, which must be "none" or "single".
+fn lookup_line_style<'a>(env: &Env<'a>, var_name: &'static str) -> Result<bool> {
+    match env.lookup_str(&Idents(vec![var_name]))?.as_ref() {
+        "none" => Ok(false),
+        "single" => Ok(true),
+        other => {
+            let mut fmt = Formatter::new();
+            fmt.print("'");
+            fmt.print(other);
+            fmt.print("' is not a valid value for '");
+            fmt.print(var_name);
+            fmt.print("'. Must be one of 'none', 'single'.");
+            Err(Error::value(fmt.into_string()))
+        }
+    }
+}
+
+impl Decorations {
+    /// Read the 'underline', 'strikethrough', and 'highlight_color' variables,
+    /// and place the lines as the font prescribes, for text of the given size.
+    fn from_env<'a>(env: &Env<'a>,
+                    ft_face: &mut freetype::Face<'static>,
+                    font_size: f64)
+                    -> Result<Decorations> {
+        let underline = lookup_line_style(env, "underline")?;
+        let strikethrough = lookup_line_style(env, "strikethrough")?;
+        let highlight = match env.lookup(&Idents(vec!["highlight_color"]))? {
+            Val::Str(ref s) if s == "none" => None,
+            _ => Some(env.lookup_paint(&Idents(vec!["highlight_color"]))?),
+        };
+
+        let units_factor = font_size / ft_face.em_size() as f64;
+
+        // The underline metrics come from the 'post' table, and give the
+        // center of the line. The y-axis of font units points up, ours points
+        // down.
+        let underline_thickness = ft_face.underline_thickness() as f64 * units_factor;
+        let underline_center = -ft_face.underline_position() as f64 * units_factor;
+
+        // The strikeout metrics come from the 'OS/2' table, and give the top of
+        // the line. If the font has none, put the line at about the height of
+        // a dash, with the thickness of the underline.
+        let (strikeout_top, strikeout_thickness) = match sfnt::strikeout_metrics(ft_face) {
+            Some((position, size)) => (-position as f64 * units_factor, size as f64 * units_factor),
+            None => (-0.25 * font_size - 0.5 * underline_thickness, underline_thickness),
+        };
+
+        let ascender = ft_face.ascender() as f64 * units_factor;
+        let descender = ft_face.descender() as f64 * units_factor;
+
+        let decorations = Decorations {
+            underline: if underline {
+                Some((underline_center - 0.5 * underline_thickness, underline_thickness))
+            } else {
+                None
+            },
+            strikethrough: if strikethrough {
+                Some((strikeout_top, strikeout_thickness))
+            } else {
+                None
+            },
+            highlight: highlight.map(|paint| (paint, -ascender, -descender)),
+        };
+        Ok(decorations)
+    }
+}
+
+/// Split text into runs of a single script, as byte ranges.
+///
+/// Characters that have no script of their own, such as spaces, punctuation,
+/// and combining marks, join the run before them, or the first run if they
+/// occur at the start of the text.
+fn split_scripts(text: &str) -> Vec<(usize, usize)> {
+    let mut runs = Vec::new();
+    let mut start = 0;
+    let mut current: Option<harfbuzz::Script> = None;
+
+    for (i, c) in text.char_indices() {
+        let script = harfbuzz::Script::of_char(c);
+        if !script.is_real() {
+            continue
+        }
+        match current {
+            Some(cur) if cur != script => {
+                runs.push((start, i));
+                start = i;
+                current = Some(script);
+            }
+            Some(..) => {}
+            None => current = Some(script),
+        }
+    }
+
+    if start < text.len() {
+        runs.push((start, text.len()));
+    }
+
+    runs
+}
+
+#[test]
+fn split_scripts_attaches_spaces_to_the_previous_run() {
+    let text = "Latin Кириллица.";
+    let runs = split_scripts(text);
+    assert_eq!(&runs, &[(0, 6), (6, text.len())]);
+}
+
+/// Split text into runs that can each be shaped with a single face, as byte
+/// ranges together with the font file.
+///
+/// Characters are set in the font for the family and style where possible.
+/// Characters it lacks continue the current run if that face has them, so
+/// e.g. emoji sequences stay together, and otherwise they are set in a
+/// fallback font. Characters that no font has stay in the current run.
+fn split_faces(fm: &mut FontMap,
+               font: &FontSpec,
+               font_file: &FontFile,
+               text: &str)
+               -> Vec<(usize, usize, FontFile)> {
+    let mut runs: Vec<(usize, usize, FontFile)> = Vec::new();
+
+    for (i, c) in text.char_indices() {
+        let face_file = if fm.has_glyph(font_file, c) {
+            font_file.clone()
+        } else {
+            let current = runs.last().map(|run| run.2.clone());
+            match current {
+                Some(ref cur) if fm.has_glyph(cur, c) => cur.clone(),
+                _ => match fm.locate_fallback(font, c) {
+                    Some(fallback) => fallback,
+                    None => current.unwrap_or(font_file.clone()),
+                },
+            }
+        };
+
+        let end = i + c.len_utf8();
+        match runs.last_mut() {
+            Some(run) if run.2 == face_file => {
+                run.1 = end;
+                continue
+            }
+            _ => {}
+        }
+        runs.push((i, end, face_file));
+    }
+
+    runs
+}
+
+/// Returns the styles that apply to `text[start..end]`, given the styles of
+/// `text` as (start offset, style) pairs, with offsets relative to `start`.
+fn slice_styles<'s>(styles: &[(usize, &'s TextStyle)],
+                    start: usize,
+                    end: usize)
+                    -> Vec<(usize, &'s TextStyle)> {
+    let mut result = Vec::new();
+    for (i, &(offset, style)) in styles.iter().enumerate() {
+        let style_end = match styles.get(i + 1) {
+            Some(&(next_offset, _)) => next_offset,
+            None => usize::MAX,
+        };
+        if style_end <= start {
+            continue
+        }
+        if offset >= end && result.len() > 0 {
+            break
+        }
+        result.push((offset.saturating_sub(start), style));
+    }
+    result
+}
+
+/// Typesets a single line of text, given the styles of the text as (start
+/// offset, style) pairs. The first style must start at offset zero.
+///
+/// The line is split into runs of a single direction using the Unicode
+/// bidirectional algorithm, and the runs are placed in visual order. The runs
+/// are further split by style, by script unless the script is set explicitly,
+/// and finally by the face that covers the characters. The pieces share a
+/// baseline, and each piece continues where the previous one ends.
+fn typeset_line(fm: &mut FontMap, styles: &[(usize, &TextStyle)], text: &str) -> Line {
+    let mut line = Line::new();
+
+    // Spans can change the style, but not the paragraph direction.
+    let bidi_info = BidiInfo::new(text, styles[0].1.shaping.base_level);
+    for para in &bidi_info.paragraphs {
+        let (levels, runs) = bidi_info.visual_runs(para, para.range.clone());
+        for run in runs {
+            let is_rtl = levels[run.start].is_rtl();
+
+            // Collect the pieces as (start, end, style, font file), in logical
+            // order.
+            let mut pieces = Vec::new();
+            let run_styles = slice_styles(styles, run.start, run.end);
+            for (i, &(offset, style)) in run_styles.iter().enumerate() {
+                let style_start = run.start + offset;
+                let style_end = match run_styles.get(i + 1) {
+                    Some(&(next_offset, _)) => run.start + next_offset,
+                    None => run.end,
+                };
+                let script_runs = match style.shaping.script {
+                    Some(..) => vec![(0, style_end - style_start)],
+                    None => split_scripts(&text[style_start..style_end]),
+                };
+                for (script_start, script_end) in script_runs {
+                    let script_start = style_start + script_start;
+                    let script_end = style_start + script_end;
+                    let face_runs = split_faces(fm,
+                                                &style.font,
+                                                &style.font_file,
+                                                &text[script_start..script_end]);
+                    for (start, end, face_file) in face_runs {
+                        pieces.push((script_start + start, script_start + end, style, face_file));
+                    }
+                }
+            }
+
+            // Within a right-to-left run, the first piece goes rightmost.
+            if is_rtl {
+                pieces.reverse();
+            }
+
+            for (start, end, style, face_file) in pieces {
+                let direction = if is_rtl {
+                    harfbuzz::Direction::RightToLeft
+                } else {
+                    harfbuzz::Direction::LeftToRight
+                };
+
+                // Shape the text using Harfbuzz: convert the UTF-8 string and
+                // input font into a list of glyphs with offsets. For
+                // right-to-left runs, Harfbuzz returns the glyphs in visual
+                // order already.
+                let shaping = &style.shaping;
+                let ft_face = fm.get_instance(&face_file, &style.variations);
+                let mut hb_font = harfbuzz::Font::from_ft_face(ft_face);
+                let mut hb_buffer = harfbuzz::Buffer::new(direction);
+                hb_buffer.add_str(&text[start..end]);
+                if let Some(script) = shaping.script {
+                    hb_buffer.set_script(script);
+                }
+                if let Some(language) = shaping.language {
+                    hb_buffer.set_language(language);
+                }
+                hb_buffer.guess_segment_properties();
+                hb_buffer.shape(&mut hb_font, &shaping.features);
+
+                line.append(style, &face_file, ft_face, &text[start..end], &hb_buffer.glyphs());
+            }
+        }
+    }
+
+    line
+}
+
+/// Split a string on newlines.
+///
+/// Unlike `std::str::lines`, the final newline is not swallowed.
+fn split_lines(text: &str) -> Vec<&str> {
+    // TODO: This might return an iterator instead of a vector.
+    // At this point it is not worth the trouble performance-wise.
+    let mut lines = Vec::new();
+    let mut left = text;
+    while let Some(index) = left.find("\n") {
+        lines.push(&left[0..index]);
+        left = &left[index + 1..];
+    }
+    lines.push(left);
+    lines
+}
+
+#[test]
+fn split_lines_returns_as_many_lines_as_newlines_plus_one() {
+    let text = "\nfoo\nbar\n";
+    let lines = split_lines(text);
+    assert_eq!(&lines, &["", "foo", "bar", ""]);
+}
+
+/// Returns the string without trailing whitespace.
+fn trim_end(text: &str) -> &str {
+    let end = text.char_indices()
+        .rev()
+        .find(|&(_, c)| !c.is_whitespace())
+        .map(|(i, c)| i + c.len_utf8())
+        .unwrap_or(0);
+    &text[..end]
+}
+
+#[test]
+fn trim_end_removes_trailing_whitespace_only() {
+    assert_eq!(trim_end(" foo bar \u{3000}\t"), " foo bar");
+    assert_eq!(trim_end("  "), "");
+}
+
+/// Read the 'hyphenate' variable, and load the patterns that it selects.
+fn load_hyphenation<'a>(fm: &mut FontMap, env: &Env<'a>) -> Result<Option<Rc<Patterns>>> {
+    let hyphenate = env.lookup_str(&Idents(vec!["hyphenate"]))?;
+    let path = match hyphenate.as_ref() {
+        "none" => return Ok(None),
+        "auto" => {
+            let language = env.lookup_str(&Idents(vec!["text_language"]))?;
+            if language == "auto" {
+                let mut fmt = Formatter::new();
+                fmt.print("'hyphenate' is 'auto', which selects patterns for ");
+                fmt.print("'text_language', but 'text_language' is 'auto' too. ");
+                fmt.print("Set 'text_language' to e.g. 'en-us'.");
+                return Err(Error::value(fmt.into_string()))
+            }
+            match Patterns::locate(&language) {
+                Some(path) => path,
+                None => {
+                    let msg = format!("No hyphenation patterns found for language '{}'. \
+                                       Set 'hyphenate' to the path of a pattern file instead.",
+                                      language);
+                    return Err(Error::Other(msg))
+                }
+            }
+        }
+        path => PathBuf::from(path),
+    };
+
+    match fm.get_patterns(&path) {
+        Some(patterns) => Ok(Some(patterns)),
+        None => Err(Error::missing_file(path.to_string_lossy().into_owned())),
+    }
+}
+
+/// A position where a line may be broken.
+#[derive(Debug, PartialEq)]
+struct Break {
+    /// The byte offset into the text of the break.
+    offset: usize,
+    /// Whether breaking here requires a hyphen.
+    hyphenate: bool,
+    /// Whether the line must be broken here, such as after a line separator.
+    /// The end of the text is a mandatory break too.
+    is_mandatory: bool,
+}
+
+/// Returns the positions where a line may be broken, in order.
+///
+/// Lines may break at Unicode line break opportunities, which include soft
+/// hyphens, and at hyphenation points if there are patterns. Words that
+/// contain soft hyphens are not hyphenated further.
+fn break_candidates(text: &str, hyphenation: Option<&Patterns>) -> Vec<Break> {
+    let mut candidates: Vec<Break> = LineBreakIterator::new(text)
+        .map(|(i, is_mandatory)| Break {
+            offset: i,
+            hyphenate: text[..i].ends_with('\u{ad}'),
+            is_mandatory: is_mandatory,
+        })
+        .collect();
+
+    if let Some(patterns) = hyphenation {
+        let mut word_start = None;
+        for (i, c) in text.char_indices().chain(iter::once((text.len(), ' '))) {
+            let is_word = c.is_alphabetic() || c == '\u{ad}';
+            match (word_start, is_word) {
+                (None, true) => word_start = Some(i),
+                (Some(start), false) => {
+                    let word = &text[start..i];
+                    if !word.contains('\u{ad}') {
+                        candidates.extend(patterns.hyphenate(word).iter().map(|&k| Break {
+                            offset: start + k,
+                            hyphenate: true,
+                            is_mandatory: false,
+                        }));
+                    }
+                    word_start = None;
+                }
+                _ => {}
+            }
+        }
+        candidates.sort_by_key(|b| b.offset);
+    }
+
+    candidates
+}
+
+#[test]
+fn break_candidates_hyphenate_at_soft_hyphens() {
+    let text = "hy\u{ad}phen ok";
+    let candidates = break_candidates(text, None);
+    let offsets: Vec<(usize, bool)> = candidates.iter().map(|b| (b.offset, b.hyphenate)).collect();
+    assert_eq!(&offsets, &[(4, true), (9, false), (text.len(), false)]);
+}
+
+#[test]
+fn break_candidates_are_mandatory_after_line_separators() {
+    let text = "foo\u{2028}bar baz";
+    let candidates = break_candidates(text, None);
+    let mandatory: Vec<usize> = candidates.iter().filter(|b| b.is_mandatory).map(|b| b.offset).collect();
+    assert_eq!(&mandatory, &[6, text.len()]);
+}
+
+/// Returns the text of a line that ends at a break candidate.
+fn line_text(text: &str, hyphenate: bool) -> String {
+    if hyphenate {
+        // Breaking at a soft hyphen makes it visible as a normal hyphen.
+        let mut result = if text.ends_with('\u{ad}') {
+            text[..text.len() - '\u{ad}'.len_utf8()].to_string()
+        } else {
+            text.to_string()
+        };
+        result.push('-');
+        result
+    } else {
+        trim_end(text).to_string()
+    }
+}
+
+/// Break a line of text into lines no wider than `width`, at Unicode line
+/// break opportunities, and at hyphenation points if there are patterns.
+/// Mandatory breaks, such as after a line separator, always end a line.
+/// Returns the lines together with the offset into the text past their end.
+///
+/// Lines are filled greedily. A word that is wider than `width` by itself gets
+/// a line of its own, and overflows.
+fn wrap_line(fm: &mut FontMap,
+             styles: &[(usize, &TextStyle)],
+             hyphenation: Option<&Patterns>,
+             text: &str,
+             width: f64)
+             -> Vec<(usize, Line)> {
+    let mut lines = Vec::new();
+
+    // The line being filled starts at `start`, and extends up to the previous
+    // candidate, which ends the line with the `hyphenate` flag if the next
+    // segment does not fit. Rather than shaping the line again for every
+    // candidate, the segments between candidates are measured one by one.
+    // Only the lines that are output are shaped as a whole, so kerning and
+    // bidirectional reordering across segments is taken into account there.
+    let mut start = 0;
+    let mut segment_start = 0;
+    let mut hyphenate = false;
+    let mut filled_width = 0.0;
+
+    for candidate in break_candidates(text, hyphenation) {
+        let end = candidate.offset;
+        let segment = &text[segment_start..end];
+        let segment_styles = slice_styles(styles, segment_start, end);
+
+        // The width of the line if it were broken at this candidate. The
+        // trailing whitespace of the segment does not count then, but a
+        // hyphen does.
+        let end_width = typeset_line(fm, &segment_styles, &line_text(segment, candidate.hyphenate)).width;
+        if segment_start > start && filled_width + end_width > width {
+            let line_styles = slice_styles(styles, start, segment_start);
+            let line = typeset_line(fm, &line_styles, &line_text(&text[start..segment_start], hyphenate));
+            lines.push((segment_start, line));
+            start = segment_start;
+            filled_width = 0.0;
+        }
+
+        if candidate.is_mandatory {
+            let line_styles = slice_styles(styles, start, end);
+            let line = typeset_line(fm, &line_styles, &line_text(&text[start..end], candidate.hyphenate));
+            lines.push((end, line));
+            start = end;
+            filled_width = 0.0;
+        } else {
+            filled_width += typeset_line(fm, &segment_styles, segment).width;
+        }
+
+        segment_start = end;
+        hyphenate = candidate.hyphenate;
+    }
+
+    if lines.is_empty() {
+        lines.push((text.len(), typeset_line(fm, styles, "")));
+    }
+
+    lines
+}
+
+/// Returns a fill for a bounding box. The shape is relative to the frame
+/// origin rather than the box, so gradients line up with those of the text.
+fn fill_box(paint: Paint, bb: &BoundingBox) -> Fill {
+    Fill {
+        paint: paint,
+        shape: Shape::Polygon(vec![
+            Vec2::new(bb.x, bb.y),
+            Vec2::new(bb.x, bb.y + bb.height),
+            Vec2::new(bb.x + bb.width, bb.y + bb.height),
+            Vec2::new(bb.x + bb.width, bb.y),
+        ]),
+    }
+}
+
+#[derive(Copy, Clone)]
+enum TextAlign { Left, Center, Right, Justify }
+
+/// The alignment and vertical metrics to place lines of text with.
+#[derive(Copy, Clone)]
+struct LineMetrics {
+    align: TextAlign,
+    line_height: f64,
+    /// The ascender and descender of the base font. The descender is negative.
+    ascender: f64,
+    descender: f64,
+}
+
+/// A line of text that is ready to be placed.
+struct TextLine {
+    line: Line,
+    /// Whether the line is the last one of a paragraph. It is not justified.
+    is_last: bool,
+    /// The offset into the text past the end of the line.
+    end: usize,
+}
+
+/// Shapes rich text into lines. If a width is given, lines are wrapped to fit
+/// a column of that width. Otherwise lines are only broken at newlines.
+fn shape_text<'a>(fm: &mut FontMap,
+                  env: &Env<'a>,
+                  spans: &[Span],
+                  width: Option<f64>)
+                  -> Result<(LineMetrics, Vec<TextLine>)> {
+    // Read the font details from the 'font_family' and 'font_style' variables,
+    // and locate the corresponding FreeType face. The line height is a bit of a
+    // problem; we could make it dimensionless and relative to the font size --
+    // which would make it scale automatically -- but then specifying absolute
+    // line heights would be a bit of a hassle. We could make it absolute, but
+    // then it does not scale automatically. Or we could allow both here:
+    // numbers have units, so we could figure out what to do. But my gut feeling
+    // is that dynamic typing will be confusing in the end.
+    let base_style = TextStyle::from_env(fm, env, &Span::plain(String::new()).style)?;
+    let hyphenation = match width {
+        Some(..) => load_hyphenation(fm, env)?,
+        None => None,
+    };
+    let line_height = env.lookup_len(&Idents(vec!["line_height"]))?;
+    let text_align = env.lookup_str(&Idents(vec!["text_align"]))?;
+    let align = match text_align.as_ref() {
+        "left" => TextAlign::Left,
+        "center" => TextAlign::Center,
+        "right" => TextAlign::Right,
+        "justify" => TextAlign::Justify,
+        other => {
+            // TODO: Move this error to an error at assignment time, not at
+            // evaluation time. More type safety is more better.
+            let mut fmt = Formatter::new();
+            fmt.print("'");
+            fmt.print(other);
+            fmt.print("' is not a valid value for 'text_align'. ");
+            fmt.print("Must be one of 'left', 'center', 'right', 'justify'.");
+            return Err(Error::value(fmt.into_string()))
+        }
+    };
+
+    // The logical extent of a line is given by the ascender and descender of
+    // the font, which are in font units. The descender is negative. Spans in
+    // a different style and fallback fonts do not affect the logical extent.
+    let (ascender, descender) = {
+        let ft_face = fm.get(&base_style.font_file);
+        let units_factor = base_style.font_size / ft_face.em_size() as f64;
+        (ft_face.ascender() as f64 * units_factor,
+         ft_face.descender() as f64 * units_factor)
+    };
+
+    // Concatenate the spans, and record where the style of every span starts.
+    let mut text = String::new();
+    let mut styles: Vec<(usize, TextStyle)> = Vec::new();
+    for span in spans {
+        if span.text.len() > 0 {
+            styles.push((text.len(), TextStyle::from_env(fm, env, &span.style)?));
+            text.push_str(&span.text);
+        }
+    }
+    if styles.len() == 0 {
+        styles.push((0, base_style));
+    }
+    let styles: Vec<(usize, &TextStyle)> = styles.iter().map(|&(i, ref s)| (i, s)).collect();
+
+    let mut text_lines = Vec::new();
+    let mut line_start = 0;
+    for text_line in split_lines(&text) {
+        let line_styles = slice_styles(&styles, line_start, line_start + text_line.len());
+
+        let lines = match width {
+            Some(w) => wrap_line(fm, &line_styles, hyphenation.as_ref().map(|p| &**p), text_line, w),
+            None => vec![(text_line.len(), typeset_line(fm, &line_styles, text_line))],
+        };
+        let num_lines = lines.len();
+
+        for (i, (end, line)) in lines.into_iter().enumerate() {
+            text_lines.push(TextLine {
+                line: line,
+                is_last: i + 1 == num_lines,
+                end: line_start + end,
+            });
+        }
+
+        line_start += text_line.len() + 1;
+    }
+
+    let metrics = LineMetrics {
+        align: align,
+        line_height: line_height,
+        ascender: ascender,
+        descender: descender,
+    };
+    Ok((metrics, text_lines))
+}
+
+/// Places lines of text in a frame, with the first baseline at the origin. If
+/// a width is given, lines are aligned within a column of that width, and
+/// otherwise they are aligned relative to the origin.
+fn place_lines<'a>(metrics: &LineMetrics, lines: Vec<TextLine>, width: Option<f64>) -> Frame<'a> {
+    let mut runs: Vec<GlyphRun> = Vec::new();
+    let mut highlights: Vec<Fill> = Vec::new();
+    let mut rules: Vec<Fill> = Vec::new();
+    let mut ink: Option<BoundingBox> = None;
+    let mut max_width: f64 = 0.0;
+    let mut min_offset: f64 = 0.0;
+    let mut cur_x = 0.0;
+    let mut cur_y = 0.0;
+    for TextLine { mut line, is_last, .. } in lines {
+        // Apply x offset to enforce text alignment.
+        let offset = match (&metrics.align, width) {
+            (&TextAlign::Left, _) => 0.0,
+            (&TextAlign::Justify, None) => 0.0,
+            (&TextAlign::Justify, Some(w)) => {
+                if !is_last { line.justify(w); }
+                0.0
+            }
+            (&TextAlign::Center, None) => line.width * -0.5,
+            (&TextAlign::Center, Some(w)) => (w - line.width) * 0.5,
+            (&TextAlign::Right, None) => line.width * -1.0,
+            (&TextAlign::Right, Some(w)) => w - line.width,
+        };
+
+        let mut line_ink = line.ink.map(|bb| bb.offset(Vec2::new(offset, cur_y)));
+        for (run, (start, end, decorations)) in line.runs.into_iter().zip(line.extents) {
+            // Decorations span the advance of the run, and are drawn in
+            // the paint of the text, except for the highlight.
+            let span_box = |top: f64, bottom: f64| {
+                BoundingBox::new(Vec2::new(offset + start, cur_y + top),
+                                 Vec2::new(end - start, bottom - top))
+            };
+            let mut boxes = Vec::new();
+            if let Some((ref paint, top, bottom)) = decorations.highlight {
+                let bb = span_box(top, bottom);
+                highlights.push(fill_box(paint.clone(), &bb));
+                boxes.push(bb);
+            }
+            for &(top, thickness) in decorations.underline.iter().chain(&decorations.strikethrough) {
+                let bb = span_box(top, top + thickness);
+                rules.push(fill_box(run.paint.clone(), &bb));
+                boxes.push(bb);
+            }
+            for bb in boxes {
+                line_ink = Some(match line_ink {
+                    Some(line_bb) => line_bb.union(&bb),
+                    None => bb,
+                });
+            }
+
+            runs.push(GlyphRun {
+                glyphs: run.glyphs.iter().map(|g| g.offset(offset, cur_y)).collect(),
+                .. run
+            });
+        }
+
+        if let Some(line_ink) = line_ink {
+            ink = Some(match ink {
+                Some(bb) => bb.union(&line_ink),
+                None => line_ink,
+            });
+        }
+
+        max_width = max_width.max(line.width);
+        min_offset = min_offset.min(offset);
+        cur_y += metrics.line_height;
+        cur_x = offset + line.width;
+    }
+
+    let text_elem = Text {
+        runs: runs,
+    };
+
+    // Highlights go behind the text, lines go over it.
+    let mut frame = Frame::new();
+    for fill in highlights {
+        frame.place_element(Vec2::zero(), Element::Fill(fill));
+    }
+    frame.place_element(Vec2::zero(), Element::Text(text_elem));
+    for fill in rules {
+        frame.place_element(Vec2::zero(), Element::Fill(fill));
+    }
+    frame.set_anchor(Vec2::new(cur_x, cur_y - metrics.line_height));
+
+    // The logical box spans from the ascender of the first line to the
+    // descender of the last line. Horizontally it spans the column if there
+    // is one, and the lines otherwise.
+    let last_baseline = cur_y - metrics.line_height;
+    let height = last_baseline + metrics.ascender - metrics.descender;
+    let bounding_box = match width {
+        Some(w) => BoundingBox::new(Vec2::new(0.0, -metrics.ascender), Vec2::new(w, height)),
+        None => BoundingBox::new(Vec2::new(min_offset, -metrics.ascender), Vec2::new(max_width, height)),
+    };
+    frame.union_boxes(&bounding_box, &ink.unwrap_or(BoundingBox::empty()));
+
+    frame
+}
+
+/// Typesets rich text into a frame. If a width is given, lines are wrapped to
+/// fit a column of that width, and they are aligned within the column.
+/// Otherwise lines are only broken at newlines, and aligned relative to the
+/// origin.
+fn typeset_text<'a>(fm: &mut FontMap,
+                    env: &Env<'a>,
+                    spans: Vec<Span>,
+                    width: Option<f64>)
+                    -> Result<Frame<'a>> {
+    let (metrics, lines) = shape_text(fm, env, &spans, width)?;
+    Ok(place_lines(&metrics, lines, width))
+}
+
+/// Converts a string or rich text argument into spans.
+fn spans_arg<'a>(fn_name: &str, val: Val<'a>, arg_num: u32) -> Result<Vec<Span>> {
+    match val {
+        Val::Str(s) => Ok(vec![Span::plain(s)]),
+        Val::Rich(spans) => Ok(spans),
+        other => Err(Error::arg_type(fn_name, ValType::Str, other.get_type(), arg_num)),
+    }
+}
+
+pub fn t<'a>(fm: &mut FontMap,
+             env: &Env<'a>,
+             mut args: Vec<Val<'a>>)
+             -> Result<Val<'a>> {
+    if args.len() != 1 {
+        return Err(Error::arity("t", 1, args.len() as u32))
+    }
+    let spans = spans_arg("t", args.remove(0), 0)?;
+
+    let frame = typeset_text(fm, env, spans, None)?;
+    Ok(Val::Frame(Rc::new(frame)))
+}
+
+pub fn paragraph<'a>(fm: &mut FontMap,
+                     env: &Env<'a>,
+                     mut args: Vec<Val<'a>>)
+                     -> Result<Val<'a>> {
+    if args.len() != 2 {
+        return Err(Error::arity("paragraph", 2, args.len() as u32))
+    }
+    let spans = spans_arg("paragraph", args.remove(0), 0)?;
+    let width = match args.remove(0) {
+        Val::Num(w, 1) => w,
+        other => return Err(Error::arg_type("paragraph", ValType::Num(1), other.get_type(), 1)),
+    };
+
+    let frame = typeset_text(fm, env, spans, Some(width))?;
+    Ok(Val::Frame(Rc::new(frame)))
+}
+
+/// Returns the part of rich text from the given offset onwards.
+fn spans_from(spans: &[Span], start: usize) -> Vec<Span> {
+    let mut result = Vec::new();
+    let mut span_start = 0;
+    for span in spans {
+        let span_end = span_start + span.text.len();
+        if span_end > start {
+            let from = start.saturating_sub(span_start);
+            result.push(Span {
+                text: span.text[from..].to_string(),
+                style: span.style.clone(),
+            });
+        }
+        span_start = span_end;
+    }
+    result
+}
+
+#[test]
+fn spans_from_splits_the_span_at_the_offset() {
+    let spans = vec![Span::plain("foo ".to_string()), Span::plain("bar".to_string())];
+    let texts = |spans: Vec<Span>| spans.into_iter().map(|s| s.text).collect::<Vec<_>>();
+    assert_eq!(texts(spans_from(&spans, 0)), vec!["foo ", "bar"]);
+    assert_eq!(texts(spans_from(&spans, 2)), vec!["o ", "bar"]);
+    assert_eq!(texts(spans_from(&spans, 5)), vec!["ar"]);
+    assert_eq!(texts(spans_from(&spans, 7)).len(), 0);
+}
+
+/// Flows lines of text into at most `count` columns of `lines_per_column`
+/// lines each. Returns the columns, and the lines that do not fit, together
+/// with the part of the text that they contain.
+fn flow_columns(mut lines: Vec<TextLine>,
+                spans: &[Span],
+                lines_per_column: usize,
+                count: usize)
+                -> (Vec<Vec<TextLine>>, Vec<TextLine>, Vec<Span>) {
+    // The count can be huge, so saturate rather than overflow.
+    let overflow_lines = lines.split_off(lines.len().min(lines_per_column.saturating_mul(count)));
+    let overflow = match lines.last() {
+        _ if overflow_lines.is_empty() => Vec::new(),
+        // The next line starts past the newline if the paragraph ended.
+        Some(last) if last.is_last => spans_from(spans, last.end + 1),
+        Some(last) => spans_from(spans, last.end),
+        None => spans.to_vec(),
+    };
+
+    let mut columns = Vec::new();
+    let mut lines = lines.into_iter();
+    while columns.len() < count {
+        let column: Vec<TextLine> = lines.by_ref().take(lines_per_column).collect();
+        if column.is_empty() {
+            break
+        }
+        columns.push(column);
+    }
+
+    (columns, overflow_lines, overflow)
+}
+
+#[test]
+fn flow_columns_fills_columns_in_order() {
+    let spans = vec![Span::plain("aa bb\ncc".to_string())];
+    let line = |end: usize, is_last: bool| TextLine { line: Line::new(), is_last: is_last, end: end };
+    let lines = || vec![line(3, false), line(5, true), line(8, true)];
+    let ends = |column: &Vec<TextLine>| column.iter().map(|l| l.end).collect::<Vec<_>>();
+
+    // All lines fit, the last column is not full.
+    let (columns, overflow_lines, overflow) = flow_columns(lines(), &spans, 2, 2);
+    assert_eq!(columns.iter().map(&ends).collect::<Vec<_>>(), vec![vec![3, 5], vec![8]]);
+    assert_eq!(overflow_lines.len(), 0);
+    assert_eq!(overflow.len(), 0);
+
+    // The third line overflows, and it starts past the newline.
+    let (columns, overflow_lines, overflow) = flow_columns(lines(), &spans, 1, 2);
+    assert_eq!(columns.iter().map(&ends).collect::<Vec<_>>(), vec![vec![3], vec![5]]);
+    assert_eq!(overflow_lines.len(), 1);
+    assert_eq!(overflow[0].text, "cc");
+
+    // Overflow within a paragraph continues right after the line.
+    let (_, _, overflow) = flow_columns(lines(), &spans, 1, 1);
+    assert_eq!(overflow[0].text, "bb\ncc");
+
+    // A huge count does not overflow.
+    let (columns, _, _) = flow_columns(lines(), &spans, usize::MAX, usize::MAX);
+    assert_eq!(columns.len(), 1);
+}
+
+/// Typesets text into a number of columns of a fixed height, which together
+/// span 'columns_width'. Text flows from the bottom of a column to the top of
+/// the next one. When 'column_overflow' is "error", text that does not fit is
+/// an error. When it is "continue", the text that does not fit is available
+/// as the 'overflow' field of the result, so it can continue elsewhere.
+///
+/// The text must be a string or rich text. A frame cannot be passed, because
+/// it holds glyphs that are placed already, which cannot be wrapped again.
+pub fn columns<'a>(fm: &mut FontMap,
+                   env: &Env<'a>,
+                   mut args: Vec<Val<'a>>)
+                   -> Result<Val<'a>> {
+    if args.len() != 4 {
+        return Err(Error::arity("columns", 4, args.len() as u32))
+    }
+    let spans = spans_arg("columns", args.remove(0), 0)?;
+    let count = match args.remove(0) {
+        Val::Num(n, 0) if n >= 1.0 && n.fract() == 0.0 => n as usize,
+        Val::Num(_, 0) => {
+            let msg = "The number of columns passed to 'columns' must be a positive integer.";
+            return Err(Error::value(msg.to_string()))
+        }
+        other => return Err(Error::arg_type("columns", ValType::Num(0), other.get_type(), 1)),
+    };
+    let gap = match args.remove(0) {
+        Val::Num(g, 1) => g,
+        other => return Err(Error::arg_type("columns", ValType::Num(1), other.get_type(), 2)),
+    };
+    let height = match args.remove(0) {
+        Val::Num(h, 1) => h,
+        other => return Err(Error::arg_type("columns", ValType::Num(1), other.get_type(), 3)),
+    };
+
+    let total_width = env.lookup_len(&Idents(vec!["columns_width"]))?;
+    let allow_overflow = match env.lookup_str(&Idents(vec!["column_overflow"]))?.as_ref() {
+        "error" => false,
+        "continue" => true,
+        other => {
+            let mut fmt = Formatter::new();
+            fmt.print("'");
+            fmt.print(other);
+            fmt.print("' is not a valid value for 'column_overflow'. ");
+            fmt.print("Must be one of 'error', 'continue'.");
+            return Err(Error::value(fmt.into_string()))
+        }
+    };
+    let width = (total_width - gap * (count - 1) as f64) / count as f64;
+    if width <= 0.0 {
+        let msg = "The gaps between columns are wider than 'columns_width'.";
+        return Err(Error::value(msg.to_string()))
+    }
+
+    let (metrics, lines) = shape_text(fm, env, &spans, Some(width))?;
+    if metrics.line_height <= 0.0 {
+        let msg = "'line_height' must be positive to fill columns.";
+        return Err(Error::value(msg.to_string()))
+    }
+
+    // A column with n lines spans n - 1 line heights, plus the ascender above
+    // the first baseline and the descender below the last one. The count can
+    // be huge, so saturate rather than overflow.
+    let free_height = height - metrics.ascender + metrics.descender;
+    let lines_per_column = if free_height < 0.0 {
+        0
+    } else {
+        ((free_height / metrics.line_height).floor() as usize).saturating_add(1)
+    };
+
+    let (columns, overflow_lines, overflow) = flow_columns(lines, &spans, lines_per_column, count);
+    if !overflow.is_empty() && !allow_overflow {
+        let mut fmt = Formatter::new();
+        fmt.print("The text passed to 'columns' does not fit, ");
+        fmt.print(overflow_lines.len());
+        fmt.print(" lines overflow. Set 'column_overflow' to \"continue\" ");
+        fmt.print("to get the remaining text as the 'overflow' field instead.");
+        return Err(Error::value(fmt.into_string()))
+    }
+
+    let mut frame = Frame::new();
+    for (i, column) in columns.into_iter().enumerate() {
+        let position = Vec2::new(i as f64 * (width + gap), 0.0);
+        let column_frame = place_lines(&metrics, column, Some(width));
+        frame.place_frame(position, &column_frame);
+        frame.set_anchor(position + column_frame.get_anchor());
+    }
+
+    // The logical box spans all columns at their full height, even when the
+    // last ones are not full.
+    let size = Vec2::new(total_width, height);
+    frame.union_bounding_box(&BoundingBox::new(Vec2::new(0.0, -metrics.ascender), size));
+    frame.put_in_env("overflow", Val::Rich(overflow));
+
+    Ok(Val::Frame(Rc::new(frame)))
+}
+
+/// The style of a bullet or numbered list, read from the environment.
+struct ListStyle {
+    /// Bullets per nesting level, or `None` for a numbered list.
+    bullets: Option<Vec<String>>,
+    number_suffix: String,
+    indent: f64,
+    hanging_indent: f64,
+    item_spacing: f64,
+    line_height: f64,
+    /// The width to wrap items at, or `None` to not wrap them.
+    width: Option<f64>,
+}
+
+impl ListStyle {
+    fn from_env<'a>(env: &Env<'a>, numbered: bool) -> Result<ListStyle> {
+        let bullets = if numbered {
+            None
+        } else {
+            let markers = match env.lookup(&Idents(vec!["list_markers"]))? {
+                Val::List(vals) => vals,
+                _ => Vec::new(),
+            };
+            let bullets: Vec<String> = markers.iter().filter_map(|val| match *val {
+                Val::Str(ref s) => Some(s.clone()),
+                _ => None,
+            }).collect();
+            if bullets.is_empty() || bullets.len() != markers.len() {
+                let msg = "'list_markers' must be a non-empty list of strings.";
+                return Err(Error::value(msg.to_string()))
+            }
+            Some(bullets)
+        };
+        let width = match env.lookup(&Idents(vec!["list_width"]))? {
+            Val::Str(ref s) if s == "auto" => None,
+            Val::Num(w, 1) => Some(w),
+            _ => {
+                let msg = "'list_width' must be 'auto' or a length.";
+                return Err(Error::value(msg.to_string()))
+            }
+        };
+        let style = ListStyle {
+            bullets: bullets,
+            number_suffix: env.lookup_str(&Idents(vec!["list_number_suffix"]))?,
+            indent: env.lookup_len(&Idents(vec!["list_indent"]))?,
+            hanging_indent: env.lookup_len(&Idents(vec!["list_hanging_indent"]))?,
+            item_spacing: env.lookup_len(&Idents(vec!["list_item_spacing"]))?,
+            line_height: env.lookup_len(&Idents(vec!["line_height"]))?,
+            width: width,
+        };
+        Ok(style)
+    }
+
+    /// Returns the marker of an item. Levels nested deeper than there are
+    /// bullets for use the last bullet.
+    fn marker(&self, level: usize, number: i64) -> String {
+        match self.bullets {
+            Some(ref bullets) => bullets[level.min(bullets.len() - 1)].clone(),
+            None => format!("{}{}", number, self.number_suffix),
+        }
+    }
+}
+
+#[test]
+fn list_style_marker_repeats_the_last_bullet() {
+    let mut style = ListStyle {
+        bullets: Some(vec!["*".to_string(), "-".to_string()]),
+        number_suffix: ")".to_string(),
+        indent: 0.0,
+        hanging_indent: 0.0,
+        item_spacing: 0.0,
+        line_height: 0.0,
+        width: None,
+    };
+    assert_eq!(style.marker(0, 1), "*");
+    assert_eq!(style.marker(2, 1), "-");
+    style.bullets = None;
+    assert_eq!(style.marker(0, 12), "12)");
+}
+
+/// An item of a list, with the nested items flattened into the list.
+struct ListItem {
+    level: usize,
+    number: i64,
+    spans: Vec<Span>,
+}
+
+/// Appends the items of a list at the given nesting level to `flat`, in the
+/// order they are typeset. An item that is a list holds the nested items of
+/// the item before it.
+fn flatten_list_items<'a>(fn_name: &str,
+                          items: Vec<Val<'a>>,
+                          level: usize,
+                          start: i64,
+                          flat: &mut Vec<ListItem>)
+                          -> Result<()> {
+    let mut number = start;
+    for item in items {
+        let spans = match item {
+            Val::List(nested) => {
+                flatten_list_items(fn_name, nested, level + 1, 1, flat)?;
+                continue
+            }
+            Val::Str(s) => vec![Span::plain(s)],
+            Val::Rich(spans) => spans,
+            other => {
+                let mut fmt = Formatter::new();
+                fmt.print("Expected items of '");
+                fmt.print(fn_name);
+                fmt.print("' to have type '");
+                fmt.print(ValType::Str);
+                fmt.print("', '");
+                fmt.print(ValType::Rich);
+                fmt.print("', or '");
+                fmt.print(ValType::List);
+                fmt.print("' for nested items, but found '");
+                fmt.print(other.get_type());
+                fmt.print("'.");
+                return Err(Error::value(fmt.into_string()))
+            }
+        };
+        flat.push(ListItem { level: level, number: number, spans: spans });
+        number += 1;
+    }
+    Ok(())
+}
+
+/// Returns the baseline of the first line of every item, given the offset of
+/// the last baseline of each item relative to its first. Items are spaced the
+/// same regardless of nesting, and there is no spacing after the last item.
+fn list_baselines(style: &ListStyle, last_line_offsets: &[f64]) -> Vec<f64> {
+    let mut y = 0.0;
+    let mut baselines = Vec::with_capacity(last_line_offsets.len());
+    for (i, offset) in last_line_offsets.iter().enumerate() {
+        if i > 0 {
+            y += style.line_height + style.item_spacing;
+        }
+        baselines.push(y);
+        y += offset;
+    }
+    baselines
+}
+
+#[test]
+fn list_items_are_spaced_evenly_around_nested_lists() {
+    let items = vec![
+        Val::Str("a".to_string()),
+        Val::List(vec![Val::Str("b".to_string()), Val::Str("c".to_string())]),
+        Val::Str("d".to_string()),
+    ];
+    let mut flat = Vec::new();
+    flatten_list_items("numbered", items, 0, 3, &mut flat).unwrap();
+    assert_eq!(flat.iter().map(|item| item.level).collect::<Vec<_>>(), vec![0, 1, 1, 0]);
+    assert_eq!(flat.iter().map(|item| item.number).collect::<Vec<_>>(), vec![3, 1, 2, 4]);
+    assert_eq!(flat[3].spans[0].text, "d");
+
+    let style = ListStyle {
+        bullets: None,
+        number_suffix: ".".to_string(),
+        indent: 0.0,
+        hanging_indent: 0.0,
+        item_spacing: 1.0,
+        line_height: 2.0,
+        width: None,
+    };
+    // The first item wraps onto a second line, 2.0 below its first one.
+    let baselines = list_baselines(&style, &[2.0, 0.0, 0.0, 0.0]);
+    assert_eq!(baselines, vec![0.0, 5.0, 8.0, 11.0]);
+}
+
+/// Typesets a bullet or numbered list into a frame, with the baseline of the
+/// first item at the origin.
+fn typeset_list<'a>(fm: &mut FontMap,
+                    env: &Env<'a>,
+                    fn_name: &str,
+                    items: Vec<Val<'a>>,
+                    start: Option<i64>)
+                    -> Result<Frame<'a>> {
+    let style = ListStyle::from_env(env, start.is_some())?;
+    let mut flat = Vec::new();
+    flatten_list_items(fn_name, items, 0, start.unwrap_or(1), &mut flat)?;
+
+    let mut typeset = Vec::with_capacity(flat.len());
+    for item in flat {
+        // The marker hangs in front of the text, so lines of a wrapped item
+        // line up with the first one, rather than with the marker. Markers
+        // are left-aligned at the indent, whatever the 'text_align'.
+        let marker_x = item.level as f64 * style.indent;
+        let text_x = marker_x + style.hanging_indent;
+        let marker_spans = [Span::plain(style.marker(item.level, item.number))];
+        let (mut marker_metrics, marker_lines) = shape_text(fm, env, &marker_spans, None)?;
+        marker_metrics.align = TextAlign::Left;
+        let marker = place_lines(&marker_metrics, marker_lines, None);
+        let text = typeset_text(fm, env, item.spans, style.width.map(|w| w - text_x))?;
+        typeset.push((marker_x, text_x, marker, text));
+    }
+
+    // The anchor of text is on the baseline of its last line.
+    let offsets: Vec<f64> = typeset.iter().map(|&(_, _, _, ref text)| text.get_anchor().y).collect();
+    let baselines = list_baselines(&style, &offsets);
+
+    let mut frame = Frame::new();
+    for ((marker_x, text_x, marker, text), y) in typeset.into_iter().zip(baselines) {
+        let text_position = Vec2::new(text_x, y);
+        frame.place_frame(Vec2::new(marker_x, y), &marker);
+        frame.place_frame(text_position, &text);
+        frame.set_anchor(text_position + text.get_anchor());
+    }
+    Ok(frame)
+}
+
+/// Typesets a bullet list. Items are strings or rich text, and an item that
+/// is a list holds nested items. The bullets per nesting level are taken from
+/// 'list_markers'.
+pub fn bullets<'a>(fm: &mut FontMap,
+                   env: &Env<'a>,
+                   mut args: Vec<Val<'a>>)
+                   -> Result<Val<'a>> {
+    validate_args("bullets", &[ValType::List], &args)?;
+    let items = match args.remove(0) {
+        Val::List(items) => items,
+        _ => unreachable!(),
+    };
+    let frame = typeset_list(fm, env, "bullets", items, None)?;
+    Ok(Val::Frame(Rc::new(frame)))
+}
+
+/// Typesets a numbered list, where the first item has the given number.
+/// Nested lists are numbered from one.
+pub fn numbered<'a>(fm: &mut FontMap,
+                    env: &Env<'a>,
+                    mut args: Vec<Val<'a>>)
+                    -> Result<Val<'a>> {
+    validate_args("numbered", &[ValType::List, ValType::Num(0)], &args)?;
+    let items = match args.remove(0) {
+        Val::List(items) => items,
+        _ => unreachable!(),
+    };
+    let start = match args.remove(0) {
+        Val::Num(n, 0) if n.fract() == 0.0 => n as i64,
+        Val::Num(..) => {
+            let msg = "The start number passed to 'numbered' must be an integer.";
+            return Err(Error::value(msg.to_string()))
+        }
+        _ => unreachable!(),
+    };
+    let frame = typeset_list(fm, env, "numbered", items, Some(start))?;
     Ok(Val::Frame(Rc::new(frame)))
 }
 
+/// Makes (rich) text bold.
+pub fn bold<'a>(_fm: &mut FontMap,
+                _env: &Env<'a>,
+                mut args: Vec<Val<'a>>)
+                -> Result<Val<'a>> {
+    if args.len() != 1 {
+        return Err(Error::arity("bold", 1, args.len() as u32))
+    }
+    let mut spans = spans_arg("bold", args.remove(0), 0)?;
+    for span in &mut spans {
+        span.style.bold = true;
+    }
+    Ok(Val::Rich(spans))
+}
+
+/// Makes (rich) text italic.
+pub fn italic<'a>(_fm: &mut FontMap,
+                  _env: &Env<'a>,
+                  mut args: Vec<Val<'a>>)
+                  -> Result<Val<'a>> {
+    if args.len() != 1 {
+        return Err(Error::arity("italic", 1, args.len() as u32))
+    }
+    let mut spans = spans_arg("italic", args.remove(0), 0)?;
+    for span in &mut spans {
+        span.style.italic = true;
+    }
+    Ok(Val::Rich(spans))
+}
+
+/// Sets the color or gradient of (rich) text. Spans that have a color already
+/// keep it, so the innermost color wins.
+pub fn with_color<'a>(_fm: &mut FontMap,
+                      _env: &Env<'a>,
+                      mut args: Vec<Val<'a>>)
+                      -> Result<Val<'a>> {
+    if args.len() != 2 {
+        return Err(Error::arity("with_color", 2, args.len() as u32))
+    }
+    let mut spans = spans_arg("with_color", args.remove(0), 0)?;
+    let paint = paint_arg("with_color", args.remove(0), 1)?;
+    for span in &mut spans {
+        if span.style.color.is_none() {
+            span.style.color = Some(paint.clone());
+        }
+    }
+    Ok(Val::Rich(spans))
+}
+
+/// Sets the font size of (rich) text. Spans that have a size already keep it,
+/// so the innermost size wins.
+pub fn with_size<'a>(_fm: &mut FontMap,
+                     _env: &Env<'a>,
+                     mut args: Vec<Val<'a>>)
+                     -> Result<Val<'a>> {
+    if args.len() != 2 {
+        return Err(Error::arity("with_size", 2, args.len() as u32))
+    }
+    let mut spans = spans_arg("with_size", args.remove(0), 0)?;
+    let size = match args.remove(0) {
+        Val::Num(s, 1) => s,
+        other => return Err(Error::arg_type("with_size", ValType::Num(1), other.get_type(), 1)),
+    };
+    for span in &mut spans {
+        if span.style.font_size.is_none() {
+            span.style.font_size = Some(size);
+        }
+    }
+    Ok(Val::Rich(spans))
+}
+
+/// Enables OpenType features for (rich) text, in addition to 'font_features'.
+/// Features of inner spans take precedence.
+pub fn with_features<'a>(_fm: &mut FontMap,
+                         _env: &Env<'a>,
+                         mut args: Vec<Val<'a>>)
+                         -> Result<Val<'a>> {
+    if args.len() != 2 {
+        return Err(Error::arity("with_features", 2, args.len() as u32))
+    }
+    let mut spans = spans_arg("with_features", args.remove(0), 0)?;
+    let features = match args.remove(0) {
+        Val::Str(f) => f,
+        other => return Err(Error::arg_type("with_features", ValType::Str, other.get_type(), 1)),
+    };
+
+    // Report invalid features here, rather than when typesetting.
+    parse_font_features(&features)?;
+
+    for span in &mut spans {
+        span.style.font_features.insert(0, features.clone());
+    }
+    Ok(Val::Rich(spans))
+}
+
+/// Registers a font file, so its family can be used in 'font_family'. Returns
+/// the family name.
+pub fn load_font<'a>(fm: &mut FontMap,
+                     _env: &Env<'a>,
+                     mut args: Vec<Val<'a>>)
+                     -> Result<Val<'a>> {
+    validate_args("load_font", &[ValType::Str], &args)?;
+    let path = match args.remove(0) {
+        Val::Str(s) => s,
+        _ => unreachable!(),
+    };
+
+    // Like images, the path is relative to the working directory.
+    if !Path::new(&path).is_file() {
+        return Err(Error::missing_file(path))
+    }
+    match fm.add_font_file(Path::new(&path)) {
+        Some(family) => Ok(Val::Str(family)),
+        None => {
+            let msg = format!("Cannot load '{}', it is not a font file.", path);
+            Err(Error::Other(msg))
+        }
+    }
+}
+
 pub fn image<'a>(_fm: &mut FontMap,
                  _env: &Env<'a>,
                  mut args: Vec<Val<'a>>)
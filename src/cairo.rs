@@ -20,6 +20,9 @@ pub enum cairo_t {}
 #[allow(non_camel_case_types)]
 enum cairo_font_face_t {}
 
+#[allow(non_camel_case_types)]
+enum cairo_pattern_t {}
+
 #[repr(C)]
 #[allow(non_camel_case_types)]
 #[derive(Copy, Clone)]
@@ -46,11 +49,21 @@ extern {
     fn cairo_pdf_surface_create(fname: *const c_char, width: f64, height: f64) -> *mut cairo_surface_t;
     fn cairo_create(surf: *mut cairo_surface_t) -> *mut cairo_t;
     fn cairo_set_source_rgb(cr: *mut cairo_t, r: f64, g: f64, b: f64);
+    fn cairo_set_source(cr: *mut cairo_t, source: *mut cairo_pattern_t);
+    fn cairo_pattern_create_linear(x0: f64, y0: f64, x1: f64, y1: f64) -> *mut cairo_pattern_t;
+    fn cairo_pattern_create_radial(cx0: f64, cy0: f64, radius0: f64, cx1: f64, cy1: f64, radius1: f64) -> *mut cairo_pattern_t;
+    fn cairo_pattern_add_color_stop_rgb(pattern: *mut cairo_pattern_t, offset: f64, r: f64, g: f64, b: f64);
+    fn cairo_pattern_destroy(pattern: *mut cairo_pattern_t);
     fn cairo_set_line_width(cr: *mut cairo_t, width: f64);
     fn cairo_move_to(cr: *mut cairo_t, x: f64, y: f64);
     fn cairo_line_to(cr: *mut cairo_t, x: f64, y: f64);
     fn cairo_close_path(cr: *mut cairo_t);
     fn cairo_rectangle(cr: *mut cairo_t, x: f64, y: f64, w: f64, h: f64);
+    fn cairo_arc(cr: *mut cairo_t, xc: f64, yc: f64, radius: f64, angle1: f64, angle2: f64);
+    fn cairo_new_sub_path(cr: *mut cairo_t);
+    fn cairo_clip(cr: *mut cairo_t);
+    fn cairo_save(cr: *mut cairo_t);
+    fn cairo_restore(cr: *mut cairo_t);
     fn cairo_stroke(cr: *mut cairo_t);
     fn cairo_fill(cr: *mut cairo_t);
     fn cairo_show_page(cr: *mut cairo_t);
@@ -65,6 +78,7 @@ extern {
     fn cairo_set_matrix(cr: *mut cairo_t, matrix: *const cairo_matrix_t);
     fn cairo_translate(cr: *mut cairo_t, tx: f64, ty: f64);
     fn cairo_scale(cr: *mut cairo_t, sx: f64, sy: f64);
+    fn cairo_transform(cr: *mut cairo_t, matrix: *const cairo_matrix_t);
 }
 
 pub struct Surface {
@@ -82,6 +96,10 @@ pub struct FontFace {
     ft_face: freetype::Face<'static>,
 }
 
+pub struct Pattern {
+    ptr: *mut cairo_pattern_t,
+}
+
 #[derive(Copy, Clone)]
 pub struct Glyph(cairo_glyph_t);
 
@@ -121,6 +139,12 @@ impl Cairo {
         unsafe { cairo_set_source_rgb(self.ptr, r, g, b) }
     }
 
+    pub fn set_source(&mut self, pattern: &Pattern) {
+        // Cairo takes its own reference to the pattern, so the pattern may be
+        // dropped after this.
+        unsafe { cairo_set_source(self.ptr, pattern.ptr) }
+    }
+
     pub fn set_line_width(&mut self, width: f64) {
         unsafe { cairo_set_line_width(self.ptr, width) }
     }
@@ -141,6 +165,29 @@ impl Cairo {
         unsafe { cairo_rectangle(self.ptr, x, y, w, h) }
     }
 
+    pub fn arc(&mut self, xc: f64, yc: f64, radius: f64, angle1: f64, angle2: f64) {
+        unsafe { cairo_arc(self.ptr, xc, yc, radius, angle1, angle2) }
+    }
+
+    pub fn new_sub_path(&mut self) {
+        unsafe { cairo_new_sub_path(self.ptr) }
+    }
+
+    /// Intersect the clip region with the current path, and clear the path.
+    pub fn clip(&mut self) {
+        unsafe { cairo_clip(self.ptr) }
+    }
+
+    /// Push the drawing state (including transform and clip) onto a stack.
+    pub fn save(&mut self) {
+        unsafe { cairo_save(self.ptr) }
+    }
+
+    /// Restore the drawing state pushed by the last call to `save()`.
+    pub fn restore(&mut self) {
+        unsafe { cairo_restore(self.ptr) }
+    }
+
     pub fn stroke(&mut self) {
         unsafe { cairo_stroke(self.ptr) }
     }
@@ -188,6 +235,13 @@ impl Cairo {
     pub fn scale(&mut self, sx: f64, sy: f64) {
         unsafe { cairo_scale(self.ptr, sx, sy) }
     }
+
+    /// Apply the matrix to the current transform, after any existing
+    /// transformation.
+    pub fn transform(&mut self, matrix: &Matrix) {
+        let &Matrix(ref mtx) = matrix;
+        unsafe { cairo_transform(self.ptr, mtx) }
+    }
 }
 
 impl Drop for Cairo {
@@ -216,6 +270,63 @@ impl Drop for FontFace {
     }
 }
 
+impl Pattern {
+    pub fn linear(x0: f64, y0: f64, x1: f64, y1: f64) -> Pattern {
+        Pattern {
+            ptr: unsafe { cairo_pattern_create_linear(x0, y0, x1, y1) }
+        }
+    }
+
+    /// Create a radial gradient between two circles.
+    pub fn radial(cx0: f64, cy0: f64, r0: f64, cx1: f64, cy1: f64, r1: f64) -> Pattern {
+        Pattern {
+            ptr: unsafe { cairo_pattern_create_radial(cx0, cy0, r0, cx1, cy1, r1) }
+        }
+    }
+
+    pub fn add_color_stop_rgb(&mut self, offset: f64, r: f64, g: f64, b: f64) {
+        unsafe { cairo_pattern_add_color_stop_rgb(self.ptr, offset, r, g, b) }
+    }
+}
+
+impl Drop for Pattern {
+    fn drop(&mut self) {
+        unsafe { cairo_pattern_destroy(self.ptr) }
+    }
+}
+
+impl Matrix {
+    /// Create an affine transform that maps (x, y) to
+    /// (xx * x + xy * y + x0, yx * x + yy * y + y0).
+    pub fn new(xx: f64, yx: f64, xy: f64, yy: f64, x0: f64, y0: f64) -> Matrix {
+        let mtx = cairo_matrix_t {
+            xx: xx,
+            yx: yx,
+            xy: xy,
+            yy: yy,
+            x0: x0,
+            y0: y0,
+        };
+        Matrix(mtx)
+    }
+
+    pub fn scale(sx: f64, sy: f64) -> Matrix {
+        Matrix::new(sx, 0.0, 0.0, sy, 0.0, 0.0)
+    }
+
+    /// Create a rotation by the given angle in radians. Because the y-axis
+    /// points down, positive angles rotate clockwise.
+    pub fn rotate(radians: f64) -> Matrix {
+        let (sin, cos) = radians.sin_cos();
+        Matrix::new(cos, sin, -sin, cos, 0.0, 0.0)
+    }
+
+    pub fn transform_point(&self, x: f64, y: f64) -> (f64, f64) {
+        let m = &self.0;
+        (m.xx * x + m.xy * y + m.x0, m.yx * x + m.yy * y + m.y0)
+    }
+}
+
 impl Glyph {
     pub fn new(index: u64, x: f64, y: f64) -> Glyph {
         let cg = cairo_glyph_t {
@@ -226,8 +337,21 @@ impl Glyph {
         Glyph(cg)
     }
 
+    /// Returns the index of the glyph in the font.
+    pub fn index(&self) -> u64 {
+        self.0.index as u64
+    }
+
     /// Make a copy of the glyph, offset by the specified amount.
     pub fn offset(&self, dx: f64, dy: f64) -> Glyph {
         Glyph::new(self.0.index as u64, self.0.x + dx, self.0.y + dy)
     }
 }
+
+#[test]
+fn matrix_rotate_turns_x_axis_into_y_axis() {
+    use std::f64::consts::PI;
+    let (x, y) = Matrix::rotate(0.5 * PI).transform_point(1.0, 0.0);
+    assert!(x.abs() < 1e-12);
+    assert!((y - 1.0).abs() < 1e-12);
+}
@@ -5,15 +5,44 @@
 // it under the terms of the GNU General Public License version 3. A copy
 // of the License is available in the root of the repository.
 
+use std::f64::consts::PI;
+
 use ast::Idents;
-use cairo::{Cairo, FontFace};
-use elements::{Color, Element, PlacedElement, Vec2};
+use cairo::{Cairo, FontFace, Pattern};
+use elements::{Element, Gradient, Paint, PlacedElement, Shape, Vec2};
 use runtime::{FontMap, Frame};
 
-fn draw_background(cr: &mut Cairo, color: Color) {
+/// Set the Cairo source to a solid color or a gradient.
+///
+/// Gradient coordinates are interpreted in the current user space, so the
+/// transform must be set up before calling this.
+fn set_source_paint(cr: &mut Cairo, paint: &Paint) {
+    let (mut pattern, stops) = match *paint {
+        Paint::Solid(color) => {
+            cr.set_source_rgb(color.r, color.g, color.b);
+            return
+        }
+        Paint::Gradient(Gradient::Linear { from, to, ref stops }) => {
+            (Pattern::linear(from.x, from.y, to.x, to.y), stops)
+        }
+        Paint::Gradient(Gradient::Radial { center, radius, ref stops }) => {
+            (Pattern::radial(center.x, center.y, 0.0, center.x, center.y, radius), stops)
+        }
+    };
+
+    // Stops are spaced evenly; the builtins ensure that there are at least two.
+    let step = 1.0 / (stops.len() - 1) as f64;
+    for (i, color) in stops.iter().enumerate() {
+        pattern.add_color_stop_rgb(i as f64 * step, color.r, color.g, color.b);
+    }
+
+    cr.set_source(&pattern);
+}
+
+fn draw_background(cr: &mut Cairo, paint: &Paint) {
     // TODO: Do not hard-code the canvas dimensions.
     cr.rectangle(0.0, 0.0, 1920.0, 1080.0);
-    cr.set_source_rgb(color.r, color.g, color.b);
+    set_source_paint(cr, paint);
     cr.fill();
 }
 
@@ -33,6 +62,31 @@ fn draw_polygon(cr: &mut Cairo, vertices: &[Vec2], close: bool) {
     }
 }
 
+/// Draw the outline of a shape as a new sub-path, but don't fill it yet.
+fn draw_shape(cr: &mut Cairo, shape: &Shape) {
+    match *shape {
+        Shape::Polygon(ref vertices) => {
+            let close = true;
+            draw_polygon(cr, vertices, close);
+        }
+        Shape::Circle { center, radius } => {
+            cr.new_sub_path();
+            cr.arc(center.x, center.y, radius, 0.0, 2.0 * PI);
+        }
+        Shape::RoundedRectangle { size, radius } => {
+            // The corners cannot be rounder than half the shortest side.
+            let r = radius.min(0.5 * size.x.abs()).min(0.5 * size.y.abs());
+            let (w, h) = (size.x, size.y);
+            cr.new_sub_path();
+            cr.arc(w - r, r, r, -0.5 * PI, 0.0);
+            cr.arc(w - r, h - r, r, 0.0, 0.5 * PI);
+            cr.arc(r, h - r, r, 0.5 * PI, PI);
+            cr.arc(r, r, r, PI, 1.5 * PI);
+            cr.close_path();
+        }
+    }
+}
+
 fn draw_element(fm: &mut FontMap, cr: &mut Cairo, pe: &PlacedElement) {
     match pe.element {
         Element::StrokePolygon(ref polygon) => {
@@ -41,59 +95,91 @@ fn draw_element(fm: &mut FontMap, cr: &mut Cairo, pe: &PlacedElement) {
 
             draw_polygon(cr, &polygon.vertices, polygon.close);
 
-            cr.set_source_rgb(polygon.color.r, polygon.color.g, polygon.color.b);
+            set_source_paint(cr, &polygon.paint);
             cr.set_line_width(polygon.line_width);
             cr.stroke();
 
             cr.set_matrix(&matrix);
         }
 
-        Element::FillPolygon(ref polygon) => {
+        Element::Fill(ref fill) => {
             let matrix = cr.get_matrix();
             cr.translate(pe.position.x, pe.position.y);
 
-            let close = true;
-            draw_polygon(cr, &polygon.vertices, close);
+            draw_shape(cr, &fill.shape);
 
-            cr.set_source_rgb(polygon.color.r, polygon.color.g, polygon.color.b);
+            set_source_paint(cr, &fill.paint);
             cr.fill();
 
             cr.set_matrix(&matrix);
         }
 
+        Element::Stroke(ref stroke) => {
+            let matrix = cr.get_matrix();
+            cr.translate(pe.position.x, pe.position.y);
+
+            draw_shape(cr, &stroke.shape);
+
+            set_source_paint(cr, &stroke.paint);
+            cr.set_line_width(stroke.line_width);
+            cr.stroke();
+
+            cr.set_matrix(&matrix);
+        }
+
         Element::Text(ref text) => {
-            // Cairo uses absolute positions for glyphs, so we need to add
-            // the final positions to the glyph locations.
-            let glyphs_offset: Vec<_> = text.glyphs.iter()
-                                            // TODO: Make offset type take
-                                            // Vec2.
-                                            .map(|g| g.offset(pe.position.x, pe.position.y))
-                                            .collect();
-            // If we were able to shape the text, then the FT font must
-            // exist still. TODO: Would it be better to just embed a
-            // reference in the Text element instead of doing the lookup
-            // twice?
-            let ft_face = fm.get(&text.font_family, &text.font_style).unwrap();
-            let cr_face = FontFace::from_ft_face(ft_face.clone());
-            cr.set_font_face(&cr_face);
-            cr.set_font_size(text.font_size);
-            cr.set_source_rgb(text.color.r, text.color.g, text.color.b);
-            cr.show_glyphs(&glyphs_offset);
-            // TODO: The cr_font should outlive the Cairo, because Cairo
-            // might internally reference the font still. How to model this?
+            // Glyph positions are relative to the text origin. Translate
+            // rather than offsetting every glyph, so that gradients are
+            // relative to the text origin too.
+            let matrix = cr.get_matrix();
+            cr.translate(pe.position.x, pe.position.y);
+
+            for run in &text.runs {
+                // If we were able to shape the text, then the font has been
+                // loaded already.
+                fm.mark_used(&run.font_file, &run.glyphs);
+                let ft_face = fm.get_instance(&run.font_file, &run.variations);
+                let cr_face = FontFace::from_ft_face(ft_face.clone());
+                cr.set_font_face(&cr_face);
+                cr.set_font_size(run.font_size);
+                set_source_paint(cr, &run.paint);
+                cr.show_glyphs(&run.glyphs);
+                // TODO: The cr_font should outlive the Cairo, because Cairo
+                // might internally reference the font still. How to model this?
+            }
+
+            cr.set_matrix(&matrix);
         }
 
-        Element::Scaled(ref elements, scale) => {
+        Element::Transformed(ref elements, ref transform) => {
             // Store the current transform so we can restore it later.
             let matrix = cr.get_matrix();
             cr.translate(pe.position.x, pe.position.y);
-            cr.scale(scale, scale);
+            cr.transform(transform);
             for inner_pe in elements {
                 draw_element(fm, cr, inner_pe);
             }
             cr.set_matrix(&matrix);
         }
 
+        Element::Clipped(ref elements, ref clip_shapes) => {
+            // Unlike the transform, the clip region can only be reset by
+            // restoring a saved state.
+            cr.save();
+            cr.translate(pe.position.x, pe.position.y);
+            for &(ref position, ref shape) in clip_shapes {
+                let matrix = cr.get_matrix();
+                cr.translate(position.x, position.y);
+                draw_shape(cr, shape);
+                cr.set_matrix(&matrix);
+            }
+            cr.clip();
+            for inner_pe in elements {
+                draw_element(fm, cr, inner_pe);
+            }
+            cr.restore();
+        }
+
         Element::Svg(ref svg) => {
             // Store the current transform so we can restore it later.
             let matrix = cr.get_matrix();
@@ -105,11 +191,11 @@ fn draw_element(fm: &mut FontMap, cr: &mut Cairo, pe: &PlacedElement) {
 }
 
 pub fn render_frame<'a>(fm: &mut FontMap, cr: &mut Cairo, frame: &Frame<'a>) {
-    // TODO: Ensure that writing to background_color only accepts a color value,
-    // so a lookup failure here is never a type error.
+    // TODO: Ensure that writing to background_color only accepts a color or
+    // gradient value, so a lookup failure here is never a type error.
     let var_bgcolor = Idents(vec!["background_color"]);
-    if let Ok(bgcolor) = frame.get_env().lookup_color(&var_bgcolor) {
-        draw_background(cr, bgcolor);
+    if let Ok(bgpaint) = frame.get_env().lookup_paint(&var_bgcolor) {
+        draw_background(cr, &bgpaint);
     }
 
     for pe in frame.get_elements() {
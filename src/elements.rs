@@ -6,6 +6,8 @@
 // of the License is available in the root of the repository.
 
 use cairo;
+use fontconfig::FontFile;
+use harfbuzz;
 use rsvg::Svg;
 use std::ops;
 
@@ -24,22 +26,47 @@ pub struct Vec2 {
 
 #[derive(Clone)]
 pub enum Element {
-    FillPolygon(FillPolygon),
+    Fill(Fill),
+    Stroke(Stroke),
     StrokePolygon(StrokePolygon),
     Text(Text),
     Svg(Svg),
-    Scaled(Vec<PlacedElement>, f64),
+    Transformed(Vec<PlacedElement>, cairo::Matrix),
+    /// Elements that are only drawn inside the union of the positioned shapes.
+    Clipped(Vec<PlacedElement>, Vec<(Vec2, Shape)>),
 }
 
 #[derive(Clone)]
-pub struct FillPolygon {
-    pub color: Color,
-    pub vertices: Vec<Vec2>,
+pub struct Fill {
+    pub paint: Paint,
+    pub shape: Shape,
+}
+
+#[derive(Clone)]
+pub struct Stroke {
+    pub paint: Paint,
+    pub line_width: f64,
+    pub shape: Shape,
+}
+
+/// A closed outline, which can be filled, stroked, or used as a clip path.
+#[derive(Clone)]
+pub enum Shape {
+    Polygon(Vec<Vec2>),
+    Circle {
+        center: Vec2,
+        radius: f64,
+    },
+    /// A rectangle with its top left corner at the origin.
+    RoundedRectangle {
+        size: Vec2,
+        radius: f64,
+    },
 }
 
 #[derive(Clone)]
 pub struct StrokePolygon {
-    pub color: Color,
+    pub paint: Paint,
     pub line_width: f64,
     pub close: bool,
     pub vertices: Vec<Vec2>,
@@ -53,11 +80,44 @@ pub struct Color {
     pub b: f64,
 }
 
+/// A source to fill or stroke with: either a solid color or a gradient.
+#[derive(Clone)]
+pub enum Paint {
+    Solid(Color),
+    Gradient(Gradient),
+}
+
+/// A gradient between evenly spaced color stops.
+///
+/// Coordinates are relative to the origin of the element that is painted.
+#[derive(Clone)]
+pub enum Gradient {
+    Linear {
+        from: Vec2,
+        to: Vec2,
+        stops: Vec<Color>,
+    },
+    Radial {
+        center: Vec2,
+        radius: f64,
+        stops: Vec<Color>,
+    },
+}
+
 #[derive(Clone)]
 pub struct Text {
-    pub color: Color,
-    pub font_family: String,
-    pub font_style: String,
+    /// The glyphs of the text, grouped into runs that share a face, size, and
+    /// paint. Glyphs are set in a fallback face when the selected font does
+    /// not cover them, and rich text consists of spans in different styles.
+    pub runs: Vec<GlyphRun>,
+}
+
+/// A sequence of glyphs from a single font file, in one size and paint.
+#[derive(Clone)]
+pub struct GlyphRun {
+    pub paint: Paint,
+    pub font_file: FontFile,
+    pub variations: Vec<harfbuzz::Variation>,
     pub font_size: f64,
     pub glyphs: Vec<cairo::Glyph>,
 }
@@ -105,3 +165,12 @@ impl Color {
         Color { r: r, g: g, b: b }
     }
 }
+
+impl Gradient {
+    pub fn stops(&self) -> &[Color] {
+        match *self {
+            Gradient::Linear { ref stops, .. } => stops,
+            Gradient::Radial { ref stops, .. } => stops,
+        }
+    }
+}
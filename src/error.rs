@@ -39,6 +39,8 @@ pub struct MissingFileError {
 pub struct MissingFontError {
     family: String,
     style: String,
+    /// The family that Fontconfig would have substituted, if any.
+    substitute: Option<String>,
 }
 
 #[derive(Debug)]
@@ -134,14 +136,27 @@ impl Error {
         f.print("' but found '");
         f.print(actual);
         f.print("', in ");
-        match arg_num {
-            0 => f.print("first"),
-            1 => f.print("second"),
-            2 => f.print("third"),
-            3 => f.print("fourth"),
-            _ => panic!("Why does your function have so many arguments?"),
+        let ordinal = match arg_num {
+            0 => "first",
+            1 => "second",
+            2 => "third",
+            3 => "fourth",
+            4 => "fifth",
+            5 => "sixth",
+            6 => "seventh",
+            7 => "eighth",
+            8 => "ninth",
+            9 => "tenth",
+            _ => "",
+        };
+        if ordinal.is_empty() {
+            f.print("argument ");
+            f.print(arg_num + 1);
+        } else {
+            f.print(ordinal);
+            f.print(" argument");
         }
-        f.print(" argument of '");
+        f.print(" of '");
         f.print(fn_name);
         f.print("'.");
         let type_error = TypeError {
@@ -172,6 +187,34 @@ impl Error {
         Error::Type(type_error)
     }
 
+    /// Like `var_type`, for variables that accept values of several types.
+    pub fn var_type_one_of(var_name: &Idents,
+                           expected: &[ValType],
+                           actual: ValType)
+                           -> Error {
+        let mut f = Formatter::new();
+        f.print("Expected '");
+        f.print(var_name);
+        f.print("' to have type ");
+        for (i, &ty) in expected.iter().enumerate() {
+            if i > 0 {
+                f.print(if i + 1 == expected.len() { " or " } else { ", " });
+            }
+            f.print("'");
+            f.print(ty);
+            f.print("'");
+        }
+        f.print(", but found '");
+        f.print(actual);
+        f.print("'.");
+        let type_error = TypeError {
+            expected: expected[0],
+            actual: actual,
+            message: f.into_string(),
+        };
+        Error::Type(type_error)
+    }
+
     pub fn value(message: String) -> Error {
         let err = ValueError {
             message: message,
@@ -183,6 +226,16 @@ impl Error {
         let err = MissingFontError {
             family: family,
             style: style,
+            substitute: None,
+        };
+        Error::MissingFont(err)
+    }
+
+    pub fn substituted_font(family: String, style: String, substitute: String) -> Error {
+        let err = MissingFontError {
+            family: family,
+            style: style,
+            substitute: Some(substitute),
         };
         Error::MissingFont(err)
     }
@@ -209,7 +262,12 @@ impl Error {
         match *self {
             Error::Arity(ref ae) => println!("{}\n", ae.message),
             Error::MissingFile(ref mf) => println!("The file '{}' does not exist.\n", mf.path),
-            Error::MissingFont(ref mf) => println!("The font '{} {}' cannot be found.\n", mf.family, mf.style),
+            Error::MissingFont(ref mf) => match mf.substitute {
+                Some(ref sub) => println!("The font '{} {}' cannot be found, \
+                                           and substituting '{}' is not allowed.\n",
+                                          mf.family, mf.style, sub),
+                None => println!("The font '{} {}' cannot be found.\n", mf.family, mf.style),
+            },
             Error::Other(ref ot) => println!("{}\n", ot),
             Error::Parse(ref pe) => println!("{}\n", pe.message),
             Error::Type(ref tye) => println!("{}\n", tye.message),
@@ -9,16 +9,20 @@ use std::ffi::{CStr, CString, OsStr};
 use std::mem;
 use std::os::raw::{c_int, c_char, c_uchar};
 use std::os::unix::ffi::OsStrExt;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::ptr;
 
+enum FcCharSet {}
 enum FcConfig {}
+enum FcFontSet {}
 enum FcPattern {}
 
 type FcBool = c_int;
 type FcChar8 = c_uchar;
+type FcChar32 = u32;
 type FcMatchKind = c_int;
 type FcResult = c_int;
+type FcSetName = c_int;
 
 // Note: this is an enum in C. We can define one in Rust, but the underlying
 // type of an enum in C is 'int', and although in Rust we can opt for u32 or u64
@@ -39,6 +43,10 @@ mod fc {
     pub const FcMatchPattern: FcMatchKind = 0;
     pub const FcMatchFont: FcMatchKind = 1;
     pub const FcMatchScan: FcMatchKind = 2;
+
+    use fontconfig::FcSetName;
+    pub const FcSetSystem: FcSetName = 0;
+    pub const FcSetApplication: FcSetName = 1;
 }
 
 #[link(name = "fontconfig")]
@@ -47,62 +55,255 @@ extern {
     fn FcConfigSubstitute(config: *mut FcConfig, pattern: *mut FcPattern, kind: FcMatchKind) -> FcBool;
     fn FcDefaultSubstitute(pattern: *mut FcPattern);
     fn FcFontMatch(config: *mut FcConfig, pattern: *mut FcPattern, result: *mut FcResult) -> *mut FcPattern;
+    fn FcPatternGetInteger(pattern: *mut FcPattern, object: *const c_char, n: c_int, result: *mut c_int) -> FcResult;
     fn FcPatternGetString(pattern: *mut FcPattern, object: *const c_char, n: c_int, result: *mut *mut FcChar8) -> FcResult;
     fn FcPatternDestroy(pattern: *mut FcPattern);
+    fn FcPatternAddCharSet(pattern: *mut FcPattern, object: *const c_char, charset: *const FcCharSet) -> FcBool;
+    fn FcCharSetCreate() -> *mut FcCharSet;
+    fn FcCharSetAddChar(charset: *mut FcCharSet, ucs4: FcChar32) -> FcBool;
+    fn FcCharSetDestroy(charset: *mut FcCharSet);
+    fn FcWeightFromOpenType(ot_weight: c_int) -> c_int;
+    fn FcConfigAppFontAddDir(config: *mut FcConfig, dir: *const FcChar8) -> FcBool;
+    fn FcConfigAppFontAddFile(config: *mut FcConfig, file: *const FcChar8) -> FcBool;
+    fn FcConfigGetFonts(config: *mut FcConfig, set: FcSetName) -> *mut FcFontSet;
+    fn FcFontSetMatch(config: *mut FcConfig, sets: *mut *mut FcFontSet, nsets: c_int, pattern: *mut FcPattern, result: *mut FcResult) -> *mut FcPattern;
+}
+
+/// Converts a weight on the OpenType scale, where 400 is regular and 700 is
+/// bold, to the Fontconfig scale, where 80 is regular and 200 is bold.
+pub fn weight_from_opentype(weight: i32) -> i32 {
+    unsafe { FcWeightFromOpenType(weight as c_int) as i32 }
+}
+
+/// Registers the fonts in a directory as application fonts.
+///
+/// Returns false if the directory could not be scanned.
+pub fn add_font_dir(dir: &Path) -> bool {
+    let dir_cstr = CString::new(dir.as_os_str().as_bytes()).unwrap();
+    unsafe { 0 != FcConfigAppFontAddDir(ptr::null_mut(), dir_cstr.as_ptr() as *const FcChar8) }
+}
+
+/// Registers a font file as an application font.
+///
+/// Returns false if the file could not be read, or is not a font.
+pub fn add_font_file(file: &Path) -> bool {
+    let file_cstr = CString::new(file.as_os_str().as_bytes()).unwrap();
+    unsafe { 0 != FcConfigAppFontAddFile(ptr::null_mut(), file_cstr.as_ptr() as *const FcChar8) }
+}
+
+/// A font face in a file. Font collections (.ttc files) contain several
+/// faces, the index selects one of them. Other font files have one face.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct FontFile {
+    /// The absolute path to the font file.
+    pub path: PathBuf,
+
+    /// The index of the face in the file.
+    pub index: isize,
+}
+
+impl FontFile {
+    /// Returns the first face in the file.
+    pub fn new(path: PathBuf) -> FontFile {
+        FontFile {
+            path: path,
+            index: 0,
+        }
+    }
+
+    /// Returns the path, and the index for faces in a font collection.
+    pub fn description(&self) -> String {
+        match self.index {
+            0 => self.path.to_string_lossy().into_owned(),
+            i => format!("{} (face {})", self.path.to_string_lossy(), i),
+        }
+    }
+}
+
+/// The font that Fontconfig matched for a query.
+#[derive(Clone)]
+pub struct FontMatch {
+    pub file: FontFile,
+
+    /// The first family name of the font.
+    pub family: String,
+
+    /// Whether the font has the family of the query. Fontconfig always
+    /// returns a font, if the family is not installed it substitutes one.
+    pub has_family: bool,
 }
 
 /// Given a Fontconfig query such as "Cantarell" or "Cantarell:bold", returns
-/// the absolute path to the corresponding font file, if it could be found.
-pub fn get_font_location(font_query: &str) -> Option<PathBuf> {
-    let mut result = None;
+/// the font file that matches best, if there are any fonts at all.
+///
+/// Application fonts of the requested family take priority over system fonts.
+pub fn get_font_location(font_query: &str) -> Option<FontMatch> {
+    unsafe {
+        let pattern = parse_query(font_query);
+        match_font(pattern, true)
+    }
+}
 
+/// Returns the font face that contains a glyph for the given character,
+/// preferring fonts that resemble the query.
+///
+/// This is used to find a fallback font when the font for the query itself
+/// does not cover the character. Fontconfig returns its best match even if no
+/// font covers the character, so the caller should check the coverage.
+pub fn get_font_location_for_char(font_query: &str, c: char) -> Option<FontFile> {
     unsafe {
-        // This is the FC_FILE constant in the C API.
-        let fc_file = CStr::from_bytes_with_nul_unchecked(b"file\0");
-
-        // Fontconfig insists on using a non-standard character type, but it
-        // only differs in signedness, which is arbitrary for characters anyway.
-        let query_cstr = CString::new(font_query).unwrap();
-        let query_char8: *const FcChar8 = mem::transmute(query_cstr.as_ptr());
-        let pattern = FcNameParse(query_char8);
-
-        // The docs say that FcConfigSubstitute must be called, although it
-        // is unclear what its purpose is.
-        let config = ptr::null_mut();
-        assert!(0 != FcConfigSubstitute(config, pattern, fc::FcMatchPattern));
-
-        // The parsed pattern might not have some properties set, such as the
-        // weight or slant. FcDefaultSubstitute fills these in.
-        FcDefaultSubstitute(pattern);
-
-        // Note: it is important that the result is initialized to "match",
-        // because FcFontMatch does not overwrite it on success for older
-        // versions of Fontconfig (2.8 at least), apparently.
-        let mut match_result = fc::FcResultMatch;
-
-        let font_match = FcFontMatch(config, pattern, &mut match_result);
-
-        if match_result == fc::FcResultMatch {
-            // Retrieve the filename from the "match", if there was one. Doing
-            // this should always succeed, otherwise there is a programming
-            // error, or allocation failure.
-            let mut fname_ptr: *mut FcChar8 = ptr::null_mut();
-            let get_result = FcPatternGetString(font_match, fc_file.as_ptr(), 0, &mut fname_ptr);
-            assert_eq!(get_result, fc::FcResultMatch);
-
-            // Do the conversion dance: from *mut c_str to PathBuf. PathBuf owns
-            // its contents: we make a copy of Fontconfig's string, so we can
-            // free it afterwards. It is an extra copy, but it is far more
-            // convenient than the alternative. Also transmute Fontconfig's
-            // signed character strings once more.
-            let fname_cstr = CStr::from_ptr(mem::transmute(fname_ptr));
-            let fname_osstr = OsStr::from_bytes(fname_cstr.to_bytes());
-            result = Some(PathBuf::from(fname_osstr));
+        // This is the FC_CHARSET constant in the C API.
+        let fc_charset = CStr::from_bytes_with_nul_unchecked(b"charset\0");
+
+        let pattern = parse_query(font_query);
+
+        // Require the character in the pattern. Fontconfig weighs coverage
+        // more heavily than the family when matching, so this selects a font
+        // that has the character, even if it is of a different family. The
+        // pattern holds its own reference to the charset.
+        let charset = FcCharSetCreate();
+        assert!(0 != FcCharSetAddChar(charset, c as FcChar32));
+        assert!(0 != FcPatternAddCharSet(pattern, fc_charset.as_ptr(), charset));
+        FcCharSetDestroy(charset);
+
+        // Do not prefer application fonts here, they have the family but
+        // likely not the character.
+        match_font(pattern, false).map(|m| m.file)
+    }
+}
+
+/// Parses a Fontconfig query into a pattern, which must be destroyed.
+unsafe fn parse_query(font_query: &str) -> *mut FcPattern {
+    // Fontconfig insists on using a non-standard character type, but it
+    // only differs in signedness, which is arbitrary for characters anyway.
+    let query_cstr = CString::new(font_query).unwrap();
+    let query_char8: *const FcChar8 = mem::transmute(query_cstr.as_ptr());
+    FcNameParse(query_char8)
+}
+
+/// Returns a string property of a pattern, such as "family". A property can
+/// have multiple values, `n` selects one of them.
+unsafe fn get_string(pattern: *mut FcPattern, object: &CStr, n: c_int) -> Option<String> {
+    let mut str_ptr: *mut FcChar8 = ptr::null_mut();
+    if FcPatternGetString(pattern, object.as_ptr(), n, &mut str_ptr) != fc::FcResultMatch {
+        return None
+    }
+    let cstr = CStr::from_ptr(str_ptr as *const c_char);
+    Some(cstr.to_string_lossy().into_owned())
+}
+
+/// Normalizes a family name for comparison. Like Fontconfig, ignore case and
+/// blanks, so "DejaVu Sans" is the same family as "dejavusans".
+fn normalize_family(family: &str) -> String {
+    family.chars().filter(|c| !c.is_whitespace()).flat_map(|c| c.to_lowercase()).collect()
+}
+
+#[test]
+fn normalize_family_ignores_case_and_blanks() {
+    assert_eq!(normalize_family("DejaVu Sans"), normalize_family("dejavusans"));
+    assert!(normalize_family("DejaVu Sans") != normalize_family("DejaVu Serif"));
+}
+
+/// Returns whether the font has the first family of the query, under any of
+/// its family names. Returns true if the query has no family.
+unsafe fn has_requested_family(query: *mut FcPattern, font: *mut FcPattern) -> bool {
+    // This is the FC_FAMILY constant in the C API.
+    let fc_family = CStr::from_bytes_with_nul_unchecked(b"family\0");
+
+    let requested = match get_string(query, fc_family, 0) {
+        Some(family) => normalize_family(&family),
+        None => return true,
+    };
+    let mut n = 0;
+    while let Some(family) = get_string(font, fc_family, n) {
+        if normalize_family(&family) == requested {
+            return true
         }
+        n += 1;
+    }
+    false
+}
 
-        FcPatternDestroy(font_match);
-        FcPatternDestroy(pattern);
+/// Returns the font that best matches the pattern, if any. Takes ownership of
+/// the pattern.
+///
+/// Fontconfig ranks application fonts no higher than system fonts, so when a
+/// system font has the same family, it may win. If `prefer_app_fonts` is set,
+/// match against the application fonts first, and use that match if it has
+/// the requested family.
+unsafe fn match_font(pattern: *mut FcPattern, prefer_app_fonts: bool) -> Option<FontMatch> {
+    let mut result = None;
+
+    // These are the FC_FILE and FC_INDEX constants in the C API.
+    let fc_file = CStr::from_bytes_with_nul_unchecked(b"file\0");
+    let fc_index = CStr::from_bytes_with_nul_unchecked(b"index\0");
+
+    // The docs say that FcConfigSubstitute must be called, although it
+    // is unclear what its purpose is.
+    let config = ptr::null_mut();
+    assert!(0 != FcConfigSubstitute(config, pattern, fc::FcMatchPattern));
+
+    // The parsed pattern might not have some properties set, such as the
+    // weight or slant. FcDefaultSubstitute fills these in.
+    FcDefaultSubstitute(pattern);
+
+    // Note: it is important that the result is initialized to "match",
+    // because FcFontMatch does not overwrite it on success for older
+    // versions of Fontconfig (2.8 at least), apparently.
+    let mut match_result = fc::FcResultMatch;
+
+    let mut font_match = ptr::null_mut();
+    let app_fonts = FcConfigGetFonts(config, fc::FcSetApplication);
+    if prefer_app_fonts && !app_fonts.is_null() {
+        let mut sets = [app_fonts];
+        let app_match = FcFontSetMatch(config, sets.as_mut_ptr(), 1, pattern, &mut match_result);
+        let is_match = !app_match.is_null() && match_result == fc::FcResultMatch;
+        if is_match && has_requested_family(pattern, app_match) {
+            font_match = app_match;
+        } else {
+            FcPatternDestroy(app_match);
+        }
+    }
+
+    if font_match.is_null() {
+        match_result = fc::FcResultMatch;
+        font_match = FcFontMatch(config, pattern, &mut match_result);
     }
 
+    if match_result == fc::FcResultMatch {
+        // Retrieve the filename from the "match", if there was one. Doing
+        // this should always succeed, otherwise there is a programming
+        // error, or allocation failure.
+        let mut fname_ptr: *mut FcChar8 = ptr::null_mut();
+        let get_result = FcPatternGetString(font_match, fc_file.as_ptr(), 0, &mut fname_ptr);
+        assert_eq!(get_result, fc::FcResultMatch);
+
+        // Do the conversion dance: from *mut c_str to PathBuf. PathBuf owns
+        // its contents: we make a copy of Fontconfig's string, so we can
+        // free it afterwards. It is an extra copy, but it is far more
+        // convenient than the alternative. Also transmute Fontconfig's
+        // signed character strings once more.
+        let fname_cstr = CStr::from_ptr(mem::transmute(fname_ptr));
+        let fname_osstr = OsStr::from_bytes(fname_cstr.to_bytes());
+
+        // The face in a font collection. Other files have only face 0.
+        let mut index: c_int = 0;
+        FcPatternGetInteger(font_match, fc_index.as_ptr(), 0, &mut index);
+
+        // This is the FC_FAMILY constant in the C API.
+        let fc_family = CStr::from_bytes_with_nul_unchecked(b"family\0");
+        result = Some(FontMatch {
+            file: FontFile {
+                path: PathBuf::from(fname_osstr),
+                index: index as isize,
+            },
+            family: get_string(font_match, fc_family, 0).unwrap_or(String::new()),
+            has_family: has_requested_family(pattern, font_match),
+        });
+    }
+
+    FcPatternDestroy(font_match);
+    FcPatternDestroy(pattern);
+
     result
 }
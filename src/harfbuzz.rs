@@ -27,6 +27,18 @@ type hb_direction_t = c_int;
 #[allow(non_camel_case_types)]
 type hb_bool_t = c_int;
 
+#[allow(non_camel_case_types)]
+type hb_script_t = c_int;
+
+#[allow(non_camel_case_types)]
+enum hb_language_impl_t {}
+
+#[allow(non_camel_case_types)]
+type hb_language_t = *const hb_language_impl_t;
+
+#[allow(non_camel_case_types)]
+enum hb_unicode_funcs_t {}
+
 #[repr(C)]
 #[allow(non_camel_case_types)]
 struct hb_glyph_info_t {
@@ -58,6 +70,7 @@ struct hb_glyph_position_t {
 
 #[repr(C)]
 #[allow(non_camel_case_types)]
+#[derive(Copy, Clone)]
 struct hb_feature_t {
     // The tag is a hb_tag_t, typedef for uint32_t. It contains the name of an
     // OpenType feature as ASCII bytes, the first character in the most
@@ -68,6 +81,15 @@ struct hb_feature_t {
     end: c_uint,
 }
 
+#[repr(C)]
+#[allow(non_camel_case_types)]
+#[derive(Copy, Clone)]
+struct hb_variation_t {
+    // The tag of a variation axis, in the same format as a feature tag.
+    tag: u32,
+    value: f32,
+}
+
 // Note: this is an enum in C. We can define one in Rust, but the underlying
 // type of an enum in C is 'int', and although in Rust we can opt for u32 or u64
 // directly, we cannot pick the platform-dependent 'int' type. So define a bunch
@@ -83,6 +105,14 @@ mod hb {
     pub const HB_DIRECTION_RTL: hb_direction_t = 5;
     pub const HB_DIRECTION_TTB: hb_direction_t = 6;
     pub const HB_DIRECTION_BTT: hb_direction_t = 7;
+
+    // Scripts are ISO 15924 tags in Harfbuzz. Only the special ones that do not
+    // denote an actual script are listed here.
+    use harfbuzz::hb_script_t;
+    pub const HB_SCRIPT_COMMON: hb_script_t = 0x5a797979; // 'Zyyy'
+    pub const HB_SCRIPT_INHERITED: hb_script_t = 0x5a696e68; // 'Zinh'
+    pub const HB_SCRIPT_UNKNOWN: hb_script_t = 0x5a7a7a7a; // 'Zzzz'
+    pub const HB_SCRIPT_INVALID: hb_script_t = 0;
 }
 
 #[link(name = "harfbuzz")]
@@ -92,11 +122,19 @@ extern {
     fn hb_buffer_create() -> *mut hb_buffer_t;
     fn hb_buffer_destroy(buffer: *mut hb_buffer_t);
     fn hb_buffer_set_direction(buffer: *mut hb_buffer_t, direction: hb_direction_t);
+    fn hb_buffer_guess_segment_properties(buffer: *mut hb_buffer_t);
+    fn hb_buffer_set_script(buffer: *mut hb_buffer_t, script: hb_script_t);
+    fn hb_buffer_set_language(buffer: *mut hb_buffer_t, language: hb_language_t);
+    fn hb_script_from_string(string: *const c_char, len: c_int) -> hb_script_t;
+    fn hb_language_from_string(string: *const c_char, len: c_int) -> hb_language_t;
+    fn hb_unicode_funcs_get_default() -> *mut hb_unicode_funcs_t;
+    fn hb_unicode_script(ufuncs: *mut hb_unicode_funcs_t, unicode: u32) -> hb_script_t;
     fn hb_buffer_add_utf8(buffer: *mut hb_buffer_t, text: *const c_char, text_len: c_int, item_offset: c_uint, item_length: c_int);
     fn hb_shape(font: *mut hb_font_t, buffer: *mut hb_buffer_t, features: *const hb_feature_t, num_features: c_uint);
     fn hb_buffer_get_glyph_infos(buffer: *mut hb_buffer_t, length: *mut c_uint) -> *mut hb_glyph_info_t;
     fn hb_buffer_get_glyph_positions(buffer: *mut hb_buffer_t, length: *mut c_uint) -> *mut hb_glyph_position_t;
     fn hb_feature_from_string(string: *const c_char, length: c_int, feature: *mut hb_feature_t) -> hb_bool_t;
+    fn hb_variation_from_string(string: *const c_char, length: c_int, variation: *mut hb_variation_t) -> hb_bool_t;
 }
 
 pub struct Font {
@@ -116,9 +154,27 @@ pub enum Direction {
     BottomToTop,
 }
 
+/// A writing system, such as Latin, Cyrillic, or Devanagari.
+#[derive(Copy, Clone, Eq, PartialEq)]
+pub struct Script(hb_script_t);
+
+/// A BCP 47 language tag, which affects language-specific shaping.
+#[derive(Copy, Clone)]
+pub struct Language(hb_language_t);
+
+/// An OpenType feature setting, such as `smcp` or `-liga`.
+#[derive(Copy, Clone)]
+pub struct Feature(hb_feature_t);
+
+/// A position on a design axis of a variable font, such as `wght=650`.
+#[derive(Copy, Clone)]
+pub struct Variation(hb_variation_t);
+
 #[derive(Copy, Clone, Debug)]
 pub struct Glyph {
     pub codepoint: u32,
+    /// The byte index in the input string of the characters this glyph is for.
+    pub cluster: u32,
     pub x_advance: i32,
     pub y_advance: i32,
     pub x_offset: i32,
@@ -180,24 +236,23 @@ impl Buffer {
         unsafe { hb_buffer_add_utf8(self.ptr, chars, count, 0, count) }
     }
 
-    pub fn shape(&mut self, font: &mut Font) {
-        // Enable default features (proper kerning).
-        // TODO: This does not appear to do anything; "Yellow" still looks awful
-        // in Cantarell (see background_color example). How do I do kerning with
-        // Harfbuzz?
-        // For now, enable old-style numerals by default instead.
-        let features = unsafe {
-            let mut fs = [mem::uninitialized()];
-            let feature = b"onum";
-            let parsed_ok = hb_feature_from_string(
-                mem::transmute(feature.as_ptr()),
-                feature.len() as c_int,
-                &mut fs[0]);
-            assert!(parsed_ok == 1);
-            fs
-        };
+    /// Fill in the script and language of the buffer based on its contents,
+    /// if they have not been set explicitly.
+    pub fn guess_segment_properties(&mut self) {
+        unsafe { hb_buffer_guess_segment_properties(self.ptr) }
+    }
+
+    pub fn set_script(&mut self, script: Script) {
+        unsafe { hb_buffer_set_script(self.ptr, script.0) }
+    }
 
-        unsafe { hb_shape(font.ptr, self.ptr, features.as_ptr(), features.len() as c_uint) }
+    pub fn set_language(&mut self, language: Language) {
+        unsafe { hb_buffer_set_language(self.ptr, language.0) }
+    }
+
+    pub fn shape(&mut self, font: &mut Font, features: &[Feature]) {
+        let hb_features: Vec<hb_feature_t> = features.iter().map(|f| f.0).collect();
+        unsafe { hb_shape(font.ptr, self.ptr, hb_features.as_ptr(), hb_features.len() as c_uint) }
     }
 
     pub fn glyphs(&mut self) -> Vec<Glyph> {
@@ -218,6 +273,7 @@ impl Buffer {
         infos.iter().zip(poss.iter()).map(|(info, pos)| {
             Glyph {
                 codepoint: info.codepoint,
+                cluster: info.cluster,
                 x_offset: pos.x_offset,
                 y_offset: pos.y_offset,
                 x_advance: pos.x_advance,
@@ -227,6 +283,116 @@ impl Buffer {
     }
 }
 
+impl Feature {
+    /// Parse a feature in the syntax that Harfbuzz understands, for example
+    /// `smcp`, `-liga`, or `aalt=2`. Returns `None` if the feature is invalid.
+    pub fn parse(feature: &str) -> Option<Feature> {
+        let mut hb_feature = hb_feature_t {
+            tag: 0,
+            value: 0,
+            start: 0,
+            end: 0,
+        };
+        let chars: *const c_char = unsafe { mem::transmute(feature.as_bytes().as_ptr()) };
+        let parsed_ok = unsafe {
+            hb_feature_from_string(chars, feature.len() as c_int, &mut hb_feature)
+        };
+        if parsed_ok == 0 {
+            None
+        } else {
+            Some(Feature(hb_feature))
+        }
+    }
+}
+
+impl Variation {
+    /// Returns the variation for an axis with a four-letter tag, such as
+    /// `b"wdth"`.
+    pub fn new(tag: &[u8; 4], value: f32) -> Variation {
+        let tag = (tag[0] as u32) << 24 | (tag[1] as u32) << 16 | (tag[2] as u32) << 8 | tag[3] as u32;
+        Variation(hb_variation_t { tag: tag, value: value })
+    }
+
+    /// Parse a variation in the syntax that Harfbuzz understands, for example
+    /// `wght=650`. Returns `None` if the variation is invalid.
+    pub fn parse(variation: &str) -> Option<Variation> {
+        let mut hb_variation = hb_variation_t {
+            tag: 0,
+            value: 0.0,
+        };
+        let chars: *const c_char = unsafe { mem::transmute(variation.as_bytes().as_ptr()) };
+        let parsed_ok = unsafe {
+            hb_variation_from_string(chars, variation.len() as c_int, &mut hb_variation)
+        };
+        if parsed_ok == 0 {
+            None
+        } else {
+            Some(Variation(hb_variation))
+        }
+    }
+
+    /// Returns the axis tag, with the first letter in the most significant
+    /// byte.
+    pub fn tag(&self) -> u32 {
+        self.0.tag
+    }
+
+    pub fn value(&self) -> f32 {
+        self.0.value
+    }
+}
+
+#[test]
+fn variation_parse_and_new_agree() {
+    let parsed = Variation::parse("wght=650").unwrap();
+    let made = Variation::new(b"wght", 650.0);
+    assert_eq!(parsed.tag(), made.tag());
+    assert_eq!(parsed.value(), made.value());
+}
+
+impl Script {
+    /// Parse an ISO 15924 script tag such as `Cyrl` or `Deva`. Returns `None`
+    /// if Harfbuzz does not know the script.
+    pub fn parse(tag: &str) -> Option<Script> {
+        let chars: *const c_char = unsafe { mem::transmute(tag.as_bytes().as_ptr()) };
+        let script = unsafe { hb_script_from_string(chars, tag.len() as c_int) };
+        match script {
+            hb::HB_SCRIPT_INVALID | hb::HB_SCRIPT_UNKNOWN => None,
+            _ => Some(Script(script)),
+        }
+    }
+
+    /// Returns the script of a character according to the Unicode database.
+    pub fn of_char(c: char) -> Script {
+        unsafe { Script(hb_unicode_script(hb_unicode_funcs_get_default(), c as u32)) }
+    }
+
+    /// Whether the script is an actual writing system, as opposed to the
+    /// "common" or "inherited" script of e.g. punctuation or combining marks,
+    /// which take on the script of the surrounding text.
+    pub fn is_real(&self) -> bool {
+        match self.0 {
+            hb::HB_SCRIPT_COMMON | hb::HB_SCRIPT_INHERITED |
+            hb::HB_SCRIPT_UNKNOWN | hb::HB_SCRIPT_INVALID => false,
+            _ => true,
+        }
+    }
+}
+
+impl Language {
+    /// Parse a BCP 47 language tag such as `tr` or `sr-Cyrl`. Returns `None`
+    /// for an empty tag.
+    pub fn parse(tag: &str) -> Option<Language> {
+        let chars: *const c_char = unsafe { mem::transmute(tag.as_bytes().as_ptr()) };
+        let language = unsafe { hb_language_from_string(chars, tag.len() as c_int) };
+        if language.is_null() {
+            None
+        } else {
+            Some(Language(language))
+        }
+    }
+}
+
 impl Drop for Buffer {
     fn drop(&mut self) {
         // Note that Harfbufzz buffers are refcounted.
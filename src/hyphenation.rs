@@ -0,0 +1,249 @@
+// Pris -- A language for designing slides
+// Copyright 2017 Ruud van Asseldonk
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License version 3. A copy
+// of the License is available in the root of the repository.
+
+//! This module implements hyphenation with Liang's algorithm, the one that TeX
+//! uses, and reads TeX hyphenation patterns.
+//!
+//! A pattern such as `a1b` or `.ex5` is a sequence of letters with priorities
+//! between them, where a missing digit means priority zero, and a dot marks
+//! the start or end of a word. To hyphenate a word, the priorities of all
+//! patterns that occur in the word are overlaid, taking the maximum for every
+//! position. A word may be broken at positions with an odd priority.
+//!
+//! Patterns can be read from the `.pat.txt` files of the hyph-utf8 project,
+//! which contain one pattern per line, or from `.tex` files that contain a
+//! `\patterns{...}` and optionally a `\hyphenation{...}` block. The latter
+//! lists exceptions: words written out with hyphens, such as `ta-ble`.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+/// The directories where TeX distributions install the hyph-utf8 patterns.
+const PATTERN_DIRS: &'static [&'static str] = &[
+    "/usr/share/texlive/texmf-dist/tex/generic/hyph-utf8/patterns/txt",
+    "/usr/share/texmf-dist/tex/generic/hyph-utf8/patterns/txt",
+    "/usr/share/texmf/tex/generic/hyph-utf8/patterns/txt",
+];
+
+pub struct Patterns {
+    /// Priorities indexed by the letters of the pattern. A pattern with n
+    /// letters has n + 1 priorities.
+    patterns: HashMap<String, Vec<u8>>,
+
+    /// The length in characters of the longest pattern.
+    max_len: usize,
+
+    /// Words with explicit break positions, as character indices.
+    exceptions: HashMap<String, Vec<usize>>,
+
+    /// The minimum number of characters before the first break in a word.
+    left_min: usize,
+
+    /// The minimum number of characters after the last break in a word.
+    right_min: usize,
+}
+
+/// Returns the contents of the TeX command `\name{...}` in `source`, if any.
+fn tex_group<'a>(source: &'a str, name: &str) -> Option<&'a str> {
+    let start = match source.find(name) {
+        Some(i) => i + name.len(),
+        None => return None,
+    };
+    let body = match source[start..].find('{') {
+        Some(i) => &source[start + i + 1..],
+        None => return None,
+    };
+    match body.find('}') {
+        Some(i) => Some(&body[..i]),
+        None => Some(body),
+    }
+}
+
+impl Patterns {
+    /// Parse patterns from the contents of a pattern file.
+    pub fn parse(source: &str) -> Patterns {
+        // Strip comments, which run from '%' to the end of the line.
+        let mut stripped = String::with_capacity(source.len());
+        for line in source.lines() {
+            stripped.push_str(line.split('%').next().unwrap());
+            stripped.push('\n');
+        }
+
+        let mut result = Patterns {
+            patterns: HashMap::new(),
+            max_len: 0,
+            exceptions: HashMap::new(),
+            left_min: 2,
+            right_min: 3,
+        };
+
+        // A file without a \patterns group consists of only patterns.
+        let patterns = tex_group(&stripped, "\\patterns").unwrap_or(&stripped);
+        for pattern in patterns.split_whitespace() {
+            result.add_pattern(pattern);
+        }
+
+        if let Some(exceptions) = tex_group(&stripped, "\\hyphenation") {
+            for exception in exceptions.split_whitespace() {
+                result.add_exception(exception);
+            }
+        }
+
+        result
+    }
+
+    /// Load patterns from a file.
+    pub fn load(path: &Path) -> Option<Patterns> {
+        let mut source = String::new();
+        match File::open(path).and_then(|mut f| f.read_to_string(&mut source)) {
+            Ok(..) => Some(Patterns::parse(&source)),
+            Err(..) => None,
+        }
+    }
+
+    /// Locate the hyph-utf8 pattern file for a language tag, such as "en-us"
+    /// or "nl". If there are no patterns for the full tag, fall back to the
+    /// primary language.
+    pub fn locate(language: &str) -> Option<PathBuf> {
+        let language = language.to_lowercase();
+        let primary = language.split('-').next().unwrap().to_string();
+
+        // Some languages have patterns only for a variant. Use the most
+        // common one for the plain tag.
+        let variant = match primary.as_ref() {
+            "de" => Some("de-1996"),
+            "el" => Some("el-monoton"),
+            "en" => Some("en-us"),
+            _ => None,
+        };
+
+        let mut candidates = vec![language.clone(), primary.clone()];
+        candidates.extend(variant.map(|v| v.to_string()));
+
+        for dir in PATTERN_DIRS {
+            for candidate in &candidates {
+                let path = Path::new(dir).join(format!("hyph-{}.pat.txt", candidate));
+                if path.is_file() {
+                    return Some(path)
+                }
+            }
+        }
+
+        None
+    }
+
+    fn add_pattern(&mut self, pattern: &str) {
+        let mut letters = String::new();
+        let mut priorities = vec![0];
+        for c in pattern.chars() {
+            match c.to_digit(10) {
+                Some(d) => *priorities.last_mut().unwrap() = d as u8,
+                None => {
+                    letters.push(c);
+                    priorities.push(0);
+                }
+            }
+        }
+
+        self.max_len = self.max_len.max(priorities.len() - 1);
+
+        // Files may contain the same letters more than once; combine them.
+        let entry = self.patterns.entry(letters).or_insert_with(|| vec![0; priorities.len()]);
+        for (e, p) in entry.iter_mut().zip(priorities) {
+            *e = (*e).max(p);
+        }
+    }
+
+    fn add_exception(&mut self, exception: &str) {
+        let mut word = String::new();
+        let mut breaks = Vec::new();
+        let mut len = 0;
+        for c in exception.chars() {
+            if c == '-' {
+                breaks.push(len);
+            } else {
+                word.push(c);
+                len += 1;
+            }
+        }
+        self.exceptions.insert(word, breaks);
+    }
+
+    /// Returns the byte offsets in `word` where it may be hyphenated.
+    pub fn hyphenate(&self, word: &str) -> Vec<usize> {
+        let chars: Vec<char> = word.chars()
+            .map(|c| c.to_lowercase().next().unwrap_or(c))
+            .collect();
+        let n = chars.len();
+
+        let char_breaks: Vec<usize> = match self.exceptions.get(&chars.iter().cloned().collect::<String>()) {
+            Some(breaks) => breaks.clone(),
+            None => {
+                // Overlay the priorities of all patterns that occur in the
+                // word, surrounded by dots to match the start and end.
+                let mut dotted = vec!['.'];
+                dotted.extend(chars.iter().cloned());
+                dotted.push('.');
+
+                let mut priorities = vec![0; dotted.len() + 1];
+                for i in 0..dotted.len() {
+                    let max_j = dotted.len().min(i + self.max_len);
+                    for j in i + 1..max_j + 1 {
+                        let letters: String = dotted[i..j].iter().cloned().collect();
+                        if let Some(ps) = self.patterns.get(&letters) {
+                            for (k, &p) in ps.iter().enumerate() {
+                                priorities[i + k] = priorities[i + k].max(p);
+                            }
+                        }
+                    }
+                }
+
+                // The priority before character k of the word is at index
+                // k + 1, because of the leading dot.
+                let min = self.left_min;
+                let max = if n > self.right_min { n - self.right_min } else { 0 };
+                (min..max + 1).filter(|&k| priorities[k + 1] % 2 == 1).collect()
+            }
+        };
+
+        // Convert character indices into byte offsets.
+        let offsets: Vec<usize> = word.char_indices().map(|(i, _)| i).collect();
+        char_breaks.iter().filter(|&&k| k > 0 && k < n).map(|&k| offsets[k]).collect()
+    }
+}
+
+#[test]
+fn hyphenate_breaks_at_odd_priorities() {
+    let patterns = Patterns::parse("% Comment.\n\\patterns{ a1b }");
+    assert_eq!(patterns.hyphenate("xxabxxx"), vec![3]);
+
+    let patterns = Patterns::parse("a1b\n2b\n");
+    assert_eq!(patterns.hyphenate("xxabxxx"), Vec::<usize>::new());
+}
+
+#[test]
+fn hyphenate_hyphenates_hyphenation() {
+    // The patterns from the example in Liang's thesis.
+    let patterns = Patterns::parse("hy3ph he2n hena4 hen5at 1na n2at 1tio 2io o2n");
+    assert_eq!(patterns.hyphenate("hyphenation"), vec![2, 6]);
+}
+
+#[test]
+fn hyphenate_respects_minimum_lengths() {
+    let patterns = Patterns::parse("a1b");
+    assert_eq!(patterns.hyphenate("abxxx"), Vec::<usize>::new());
+    assert_eq!(patterns.hyphenate("xxxab"), Vec::<usize>::new());
+}
+
+#[test]
+fn hyphenate_uses_exceptions_and_returns_byte_offsets() {
+    let patterns = Patterns::parse("\\patterns{} \\hyphenation{ta-ble über-all}");
+    assert_eq!(patterns.hyphenate("Table"), vec![2]);
+    assert_eq!(patterns.hyphenate("überall"), vec![5]);
+}
@@ -8,13 +8,14 @@
 use std::rc::Rc;
 
 use ast;
-use ast::{Assign, BinOp, BinTerm, Block, Coord, FnCall, FnDef, Idents};
-use ast::{Num, PutAt, Return, Stmt, Term, UnOp, UnTerm, Unit};
+use ast::{Assign, BinOp, BinTerm, Block, Constraint, Coord, FnCall, FnDef, Idents};
+use ast::{Num, PutAt, Relation, Return, Stmt, Term, UnOp, UnTerm, Unit};
 use error::{Error, Result};
 use elements::{Color, Vec2};
+use layout;
+use markup;
 use pretty::Formatter;
-use runtime::{Builtin, FontMap, Frame, Env, Val};
-use types::ValType;
+use runtime::{Builtin, FontMap, Frame, Env, Span, Val};
 
 // Expression interpreter.
 
@@ -24,15 +25,26 @@ fn eval_expr<'a>(fm: &mut FontMap,
                  -> Result<Val<'a>> {
     match *term {
         Term::String(ref s) => Ok(Val::Str(s.clone())),
+        Term::PrefixedString(p, ref s) => eval_prefixed_string(p, s),
         Term::Number(ref x) => eval_num(env, x),
         Term::Color(ref co) => Ok(eval_color(co)),
         Term::Idents(ref i) => env.lookup(i),
         Term::Coord(ref co) => eval_coord(fm, env, co),
+        Term::List(ref elems) => eval_list(fm, env, elems),
         Term::BinOp(ref bo) => eval_binop(fm, env, bo),
         Term::UnOp(ref uop) => eval_unop(fm, env, uop),
         Term::FnCall(ref f) => eval_call(fm, env, f),
         Term::FnDef(ref fd) => Ok(Val::FnExtrin(fd)),
         Term::Block(ref bk) => eval_block(fm, env, bk),
+        Term::Layout(ref bk) => eval_layout(fm, env, bk),
+    }
+}
+
+fn eval_prefixed_string<'a>(prefix: &str, string: &str) -> Result<Val<'a>> {
+    match prefix {
+        "md" => Ok(Val::Rich(markup::parse(string))),
+        // The lexer only produces known prefixes.
+        _ => unreachable!(),
     }
 }
 
@@ -78,6 +90,17 @@ fn eval_coord<'a>(fm: &mut FontMap,
     }
 }
 
+fn eval_list<'a>(fm: &mut FontMap,
+                 env: &Env<'a>,
+                 elems: &'a [Term<'a>])
+                 -> Result<Val<'a>> {
+    let mut vals = Vec::with_capacity(elems.len());
+    for elem in elems {
+        vals.push(eval_expr(fm, env, elem)?);
+    }
+    Ok(Val::List(vals))
+}
+
 fn eval_binop<'a>(fm: &mut FontMap,
                   env: &Env<'a>,
                   binop: &'a BinTerm<'a>)
@@ -94,7 +117,7 @@ fn eval_binop<'a>(fm: &mut FontMap,
     }
 }
 
-/// Adjoins two frames.
+/// Adjoins two frames, or concatenates strings and rich text into rich text.
 fn eval_adj<'a>(lhs: Val<'a>, rhs: Val<'a>) -> Result<Val<'a>> {
     match (lhs, rhs) {
         (Val::Frame(f0), Val::Frame(f1)) => {
@@ -104,11 +127,34 @@ fn eval_adj<'a>(lhs: Val<'a>, rhs: Val<'a>) -> Result<Val<'a>> {
                 frame.place_element(anchor + pe.position, pe.element.clone());
             }
             frame.set_anchor(anchor + f1.get_anchor());
-            frame.union_bounding_box(&f1.get_bounding_box().offset(anchor));
+            frame.union_boxes(&f1.get_bounding_box().offset(anchor),
+                              &f1.get_ink_box().offset(anchor));
             Ok(Val::Frame(Rc::new(frame)))
         }
+        (Val::Str(a), Val::Str(b)) => {
+            Ok(Val::Rich(vec![Span::plain(a), Span::plain(b)]))
+        }
+        (Val::Str(a), Val::Rich(mut b)) => {
+            b.insert(0, Span::plain(a));
+            Ok(Val::Rich(b))
+        }
+        (Val::Rich(mut a), Val::Str(b)) => {
+            a.push(Span::plain(b));
+            Ok(Val::Rich(a))
+        }
+        (Val::Rich(mut a), Val::Rich(b)) => {
+            a.extend(b);
+            Ok(Val::Rich(a))
+        }
         (lhs, rhs) => {
-            Err(Error::binop_type("~", ValType::Frame, lhs.get_type(), rhs.get_type()))
+            let mut f = Formatter::new();
+            f.print("'~' expects operands of type 'frame' and 'frame', \
+                     or operands that are each 'str' or 'rich', but found '");
+            f.print(lhs.get_type());
+            f.print("' and '");
+            f.print(rhs.get_type());
+            f.print("' instead.");
+            Err(Error::Other(f.into_string()))
         }
     }
 }
@@ -285,6 +331,194 @@ fn eval_block<'a>(fm: &mut FontMap,
     Ok(Val::Frame(Rc::new(frame)))
 }
 
+/// Evaluates a layout block. Frames assigned in the block are laid out: their
+/// positions are chosen such that all constraints in the block hold. Frames
+/// that are not fully constrained stay as close to the origin as possible,
+/// where frames assigned earlier take precedence.
+fn eval_layout<'a>(fm: &mut FontMap,
+                   env: &Env<'a>,
+                   block: &'a Block<'a>)
+                   -> Result<Val<'a>> {
+    let mut frame = Frame::from_env(env.clone());
+    let mut frames: Vec<(&'a str, Rc<Frame<'a>>)> = Vec::new();
+    let mut constraints = Vec::new();
+
+    for statement in &block.0 {
+        match *statement {
+            Stmt::Assign(Assign(name, ref expression)) => {
+                if frames.iter().any(|&(n, _)| n == name) {
+                    let mut fmt = Formatter::new();
+                    fmt.print("Cannot assign to '");
+                    fmt.print(name);
+                    fmt.print("' in a layout block, because it is a frame to lay out.");
+                    return Err(Error::Other(fmt.into_string()))
+                }
+                let value = eval_expr(fm, frame.get_env(), expression)?;
+                if let Val::Frame(ref f) = value {
+                    frames.push((name, f.clone()));
+                }
+                frame.put_in_env(name, value);
+            }
+            Stmt::Constraint(Constraint(ref lhs, relation, ref rhs)) => {
+                let lhs = eval_layout_length(fm, frame.get_env(), &frames, lhs)?;
+                let rhs = eval_layout_length(fm, frame.get_env(), &frames, rhs)?;
+                let relation = match relation {
+                    Relation::Eq => layout::Relation::Eq,
+                    Relation::Le => layout::Relation::Le,
+                    Relation::Ge => layout::Relation::Ge,
+                };
+                constraints.push((lhs, relation, rhs));
+            }
+            _ => {
+                let msg = "Error: a layout block can only contain assignments \
+                           and constraints, such as 'a.left == b.right'.";
+                return Err(Error::Other(String::from(msg)));
+            }
+        }
+    }
+
+    // Every frame has two variables: the x and y coordinate of its origin.
+    let mut solver = layout::Solver::new(frames.len() * 2);
+    for (lhs, relation, rhs) in constraints {
+        solver.add_constraint(lhs, relation, &rhs);
+    }
+    let values = match solver.solve() {
+        Some(values) => values,
+        None => {
+            let msg = "The constraints in the layout block cannot all be satisfied.";
+            return Err(Error::Other(String::from(msg)));
+        }
+    };
+
+    // The anchor of a layout is the anchor of the frame assigned last, as for
+    // a block it is the anchor of the frame placed last.
+    for (i, &(_, ref content)) in frames.iter().enumerate() {
+        let pos = Vec2::new(values[i * 2], values[i * 2 + 1]);
+        frame.place_frame(pos, content);
+        frame.set_anchor(pos + content.get_anchor());
+    }
+
+    Ok(Val::Frame(Rc::new(frame)))
+}
+
+/// A value in a layout constraint: a number, or a length that may depend on
+/// the positions of the frames in the layout.
+enum Linear {
+    Num(f64),
+    Len(layout::Expr),
+}
+
+/// Evaluates one side of a layout constraint, which must be a length.
+fn eval_layout_length<'a>(fm: &mut FontMap,
+                          env: &Env<'a>,
+                          frames: &[(&'a str, Rc<Frame<'a>>)],
+                          term: &'a Term<'a>)
+                          -> Result<layout::Expr> {
+    match eval_linear(fm, env, frames, term)? {
+        Linear::Len(expr) => Ok(expr),
+        Linear::Num(..) => {
+            let mut fmt = Formatter::new();
+            fmt.print("Type error: both sides of a layout constraint must be lengths, ");
+            fmt.print("but '");
+            fmt.print(term);
+            fmt.print("' is a number.");
+            Err(Error::Other(fmt.into_string()))
+        }
+    }
+}
+
+/// Returns the edge of a frame in a layout, such as 'title.left', in terms of
+/// the position of the frame. Returns `None` if the identifiers do not refer
+/// to an edge of a frame in the layout.
+fn eval_edge<'a>(frames: &[(&'a str, Rc<Frame<'a>>)],
+                 idents: &Idents<'a>)
+                 -> Option<layout::Expr> {
+    if idents.0.len() != 2 {
+        return None
+    }
+    let i = match frames.iter().position(|&(name, _)| name == idents.0[0]) {
+        Some(i) => i,
+        None => return None,
+    };
+    let bb = frames[i].1.get_bounding_box();
+    let (x, y) = (i * 2, i * 2 + 1);
+    match idents.0[1] {
+        "left" => Some(layout::Expr::var(x, bb.x)),
+        "hcenter" => Some(layout::Expr::var(x, bb.x + 0.5 * bb.width)),
+        "right" => Some(layout::Expr::var(x, bb.x + bb.width)),
+        "top" => Some(layout::Expr::var(y, bb.y)),
+        "vcenter" => Some(layout::Expr::var(y, bb.y + 0.5 * bb.height)),
+        "bottom" => Some(layout::Expr::var(y, bb.y + bb.height)),
+        "baseline" => Some(layout::Expr::var(y, 0.0)),
+        _ => None,
+    }
+}
+
+fn eval_linear<'a>(fm: &mut FontMap,
+                   env: &Env<'a>,
+                   frames: &[(&'a str, Rc<Frame<'a>>)],
+                   term: &'a Term<'a>)
+                   -> Result<Linear> {
+    let type_error = |message: &str| {
+        let mut fmt = Formatter::new();
+        fmt.print("Type error in layout constraint '");
+        fmt.print(term);
+        fmt.print("': ");
+        fmt.print(message);
+        Err(Error::Other(fmt.into_string()))
+    };
+
+    match *term {
+        Term::Idents(ref idents) => {
+            if let Some(expr) = eval_edge(frames, idents) {
+                return Ok(Linear::Len(expr))
+            }
+        }
+        Term::BinOp(ref bt) => {
+            let BinTerm(ref lhs, op, ref rhs) = **bt;
+            let lhs = eval_linear(fm, env, frames, lhs)?;
+            let rhs = eval_linear(fm, env, frames, rhs)?;
+            return match (op, lhs, rhs) {
+                (BinOp::Add, Linear::Num(x), Linear::Num(y)) => Ok(Linear::Num(x + y)),
+                (BinOp::Add, Linear::Len(x), Linear::Len(y)) => Ok(Linear::Len(x.add(&y))),
+                (BinOp::Sub, Linear::Num(x), Linear::Num(y)) => Ok(Linear::Num(x - y)),
+                (BinOp::Sub, Linear::Len(x), Linear::Len(y)) => Ok(Linear::Len(x.sub(&y))),
+                (BinOp::Add, _, _) | (BinOp::Sub, _, _) => {
+                    type_error("cannot add or subtract a number and a length.")
+                }
+                (BinOp::Mul, Linear::Num(x), Linear::Num(y)) => Ok(Linear::Num(x * y)),
+                (BinOp::Mul, Linear::Num(x), Linear::Len(y)) => Ok(Linear::Len(y.scale(x))),
+                (BinOp::Mul, Linear::Len(x), Linear::Num(y)) => Ok(Linear::Len(x.scale(y))),
+                (BinOp::Div, Linear::Num(x), Linear::Num(y)) => Ok(Linear::Num(x / y)),
+                (BinOp::Div, Linear::Len(x), Linear::Num(y)) => Ok(Linear::Len(x.scale(1.0 / y))),
+                (BinOp::Mul, _, _) | (BinOp::Div, _, _) => {
+                    type_error("constraints must be linear, so lengths can only be \
+                                multiplied or divided by numbers.")
+                }
+                (BinOp::Adj, _, _) | (BinOp::Exp, _, _) => {
+                    type_error("only '+', '-', '*' and '/' can be used in constraints.")
+                }
+            }
+        }
+        Term::UnOp(ref ut) => {
+            let UnTerm(UnOp::Neg, ref rhs) = **ut;
+            return match eval_linear(fm, env, frames, rhs)? {
+                Linear::Num(x) => Ok(Linear::Num(-x)),
+                Linear::Len(x) => Ok(Linear::Len(x.scale(-1.0))),
+            }
+        }
+        _ => {}
+    }
+
+    // Anything that is not an edge or an arithmetic operation is evaluated as
+    // usual, and it must be a number or length.
+    match eval_expr(fm, env, term)? {
+        Val::Num(x, 0) => Ok(Linear::Num(x)),
+        Val::Num(x, 1) => Ok(Linear::Len(layout::Expr::constant(x))),
+        _ => type_error("expected a number or length."),
+    }
+}
+
 // Statement interpreter.
 
 pub fn eval_statement<'a>(fm: &mut FontMap,
@@ -318,6 +552,11 @@ pub fn eval_statement<'a>(fm: &mut FontMap,
             eval_put_at(fm, frame, pa)?;
             Ok(None)
         }
+        Stmt::Constraint(..) => {
+            let msg = "Error: constraints can only be used in a layout block. \
+                       Note: use 'layout { ... }' to lay out frames with constraints.";
+            Err(Error::Other(String::from(msg)))
+        }
     }
 }
 
@@ -357,7 +596,8 @@ fn eval_put_at<'a>(fm: &mut FontMap,
         frame.place_element(pos + pe.position, pe.element.clone());
     }
 
-    frame.union_bounding_box(&content.get_bounding_box().offset(pos));
+    frame.union_boxes(&content.get_bounding_box().offset(pos),
+                      &content.get_ink_box().offset(pos));
 
     // Update the anchor of the frame: the anchor of a block is the anchor of
     // the element that was placed last.
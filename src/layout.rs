@@ -0,0 +1,319 @@
+// Pris -- A language for designing slides
+// Copyright 2017 Ruud van Asseldonk
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License version 3. A copy
+// of the License is available in the root of the repository.
+
+//! This module implements a small linear constraint solver for layout blocks,
+//! in the style of Cassowary.
+//!
+//! Like Cassowary, it distinguishes required constraints from preferences.
+//! Required constraints are linear equalities and inequalities between the
+//! variables. They are solved with the simplex method, and when they cannot
+//! all be satisfied, solving fails. Layouts are often underconstrained: if
+//! nothing pins a frame down, any position satisfies the constraints. To pick
+//! one, every variable has a weak preference to be zero, and the preferences
+//! are ordered: earlier variables take precedence over later ones. This
+//! lexicographic optimum is found by minimizing the absolute value of every
+//! variable in turn, and then fixing it to the value found.
+//!
+//! Layouts have a handful of frames, so the solver uses a dense tableau and
+//! solves from scratch, rather than incrementally like Cassowary does.
+
+use std::collections::BTreeMap;
+
+/// Values smaller than this are considered zero.
+const EPSILON: f64 = 1e-7;
+
+/// A linear expression: a sum of variables with coefficients, plus a constant.
+#[derive(Clone, Debug)]
+pub struct Expr {
+    /// Coefficients indexed by variable.
+    terms: BTreeMap<usize, f64>,
+    constant: f64,
+}
+
+impl Expr {
+    pub fn constant(value: f64) -> Expr {
+        Expr {
+            terms: BTreeMap::new(),
+            constant: value,
+        }
+    }
+
+    /// Returns the variable plus a constant.
+    pub fn var(var: usize, offset: f64) -> Expr {
+        let mut terms = BTreeMap::new();
+        terms.insert(var, 1.0);
+        Expr {
+            terms: terms,
+            constant: offset,
+        }
+    }
+
+    pub fn add(mut self, other: &Expr) -> Expr {
+        for (&var, &coef) in &other.terms {
+            *self.terms.entry(var).or_insert(0.0) += coef;
+        }
+        self.constant += other.constant;
+        self
+    }
+
+    pub fn scale(mut self, factor: f64) -> Expr {
+        for coef in self.terms.values_mut() {
+            *coef *= factor;
+        }
+        self.constant *= factor;
+        self
+    }
+
+    pub fn sub(self, other: &Expr) -> Expr {
+        self.add(&other.clone().scale(-1.0))
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Relation {
+    Eq,
+    Le,
+    Ge,
+}
+
+/// A required constraint of the form `expr <relation> 0`.
+struct Constraint {
+    expr: Expr,
+    relation: Relation,
+}
+
+pub struct Solver {
+    num_vars: usize,
+    constraints: Vec<Constraint>,
+}
+
+impl Solver {
+    pub fn new(num_vars: usize) -> Solver {
+        Solver {
+            num_vars: num_vars,
+            constraints: Vec::new(),
+        }
+    }
+
+    /// Require that `lhs <relation> rhs`.
+    pub fn add_constraint(&mut self, lhs: Expr, relation: Relation, rhs: &Expr) {
+        self.constraints.push(Constraint {
+            expr: lhs.sub(rhs),
+            relation: relation,
+        });
+    }
+
+    /// Returns the values of the variables, or `None` if the constraints
+    /// cannot all be satisfied.
+    pub fn solve(&self) -> Option<Vec<f64>> {
+        let mut values: Vec<f64> = Vec::with_capacity(self.num_vars);
+        for var in 0..self.num_vars {
+            match self.minimize_abs(var, &values) {
+                Some(value) => values.push(value),
+                None => return None,
+            }
+        }
+        Some(values)
+    }
+
+    /// Returns the value closest to zero of a variable, subject to the
+    /// constraints, and to the earlier variables having the given values.
+    ///
+    /// The variables are free, but the simplex method requires nonnegative
+    /// variables, so every variable v is split into v = p - n. Column i is p
+    /// and column num_vars + i is n. Inequalities get a slack column each.
+    fn minimize_abs(&self, var: usize, fixed: &[f64]) -> Option<f64> {
+        let n = self.num_vars;
+        let num_slacks = self.constraints.iter().filter(|c| c.relation != Relation::Eq).count();
+        let num_columns = 2 * n + num_slacks;
+
+        let mut a = Vec::new();
+        let mut b = Vec::new();
+        let mut slack = 2 * n;
+        for constraint in &self.constraints {
+            let mut row = vec![0.0; num_columns];
+            for (&v, &coef) in &constraint.expr.terms {
+                row[v] = coef;
+                row[n + v] = -coef;
+            }
+            match constraint.relation {
+                Relation::Eq => {}
+                Relation::Le => { row[slack] = 1.0; slack += 1; }
+                Relation::Ge => { row[slack] = -1.0; slack += 1; }
+            }
+            a.push(row);
+            b.push(-constraint.expr.constant);
+        }
+        for (v, &value) in fixed.iter().enumerate() {
+            let mut row = vec![0.0; num_columns];
+            row[v] = 1.0;
+            row[n + v] = -1.0;
+            a.push(row);
+            b.push(value);
+        }
+
+        let mut cost = vec![0.0; num_columns];
+        cost[var] = 1.0;
+        cost[n + var] = 1.0;
+
+        minimize(&a, &b, &cost).map(|z| z[var] - z[n + var])
+    }
+}
+
+/// Minimizes `cost · z` subject to `a z = b` and `z >= 0`, with the two-phase
+/// simplex method. Returns `None` if the problem is infeasible or unbounded.
+fn minimize(a: &[Vec<f64>], b: &[f64], cost: &[f64]) -> Option<Vec<f64>> {
+    let m = a.len();
+    let n = cost.len();
+
+    // The tableau has a row per constraint, with the columns of the variables,
+    // an artificial variable per row, and the right hand side. Rows are
+    // negated where needed to make the right hand side nonnegative, so the
+    // artificial variables form a feasible starting basis.
+    let mut tableau: Vec<Vec<f64>> = a.iter().zip(b).enumerate().map(|(i, (row, &rhs))| {
+        let sign = if rhs < 0.0 { -1.0 } else { 1.0 };
+        let mut t_row: Vec<f64> = row.iter().map(|x| sign * x).collect();
+        t_row.extend((0..m).map(|j| if i == j { 1.0 } else { 0.0 }));
+        t_row.push(sign * rhs);
+        t_row
+    }).collect();
+    let mut basis: Vec<usize> = (n..n + m).collect();
+
+    // Phase one: find a feasible solution by minimizing the artificials.
+    let mut phase_one_cost = vec![0.0; n + m];
+    for c in &mut phase_one_cost[n..] {
+        *c = 1.0;
+    }
+    if run_simplex(&mut tableau, &mut basis, &phase_one_cost, n + m).is_none() {
+        return None
+    }
+    let infeasibility: f64 = basis.iter().enumerate()
+        .filter(|&(_, &col)| col >= n)
+        .map(|(i, _)| tableau[i][n + m])
+        .sum();
+    if infeasibility > EPSILON {
+        return None
+    }
+
+    // Drive artificials that are still in the basis (at zero) out of it. If a
+    // row has no other nonzero entries, it is redundant and the artificial
+    // can stay, as it will never enter again.
+    for i in 0..m {
+        if basis[i] >= n {
+            if let Some(col) = (0..n).find(|&j| tableau[i][j].abs() > EPSILON) {
+                pivot(&mut tableau, i, col);
+                basis[i] = col;
+            }
+        }
+    }
+
+    // Phase two: optimize the actual cost, with the artificials excluded.
+    let mut phase_two_cost = cost.to_vec();
+    phase_two_cost.extend((0..m).map(|_| 0.0));
+    if run_simplex(&mut tableau, &mut basis, &phase_two_cost, n).is_none() {
+        return None
+    }
+
+    let mut z = vec![0.0; n];
+    for (i, &col) in basis.iter().enumerate() {
+        if col < n {
+            z[col] = tableau[i][n + m];
+        }
+    }
+    Some(z)
+}
+
+/// Pivots until the cost is minimal. Only the first `num_entering` columns may
+/// enter the basis. Uses Bland's rule, which cannot cycle. Returns `None` if
+/// the cost is unbounded.
+fn run_simplex(tableau: &mut [Vec<f64>], basis: &mut [usize], cost: &[f64], num_entering: usize) -> Option<()> {
+    let rhs = cost.len();
+    loop {
+        let entering = {
+            let reduced_cost = |j: usize| {
+                cost[j] - basis.iter().enumerate().map(|(i, &col)| cost[col] * tableau[i][j]).sum::<f64>()
+            };
+            match (0..num_entering).find(|&j| !basis.contains(&j) && reduced_cost(j) < -EPSILON) {
+                Some(j) => j,
+                None => return Some(()),
+            }
+        };
+
+        let mut leaving: Option<usize> = None;
+        for i in 0..tableau.len() {
+            if tableau[i][entering] <= EPSILON {
+                continue
+            }
+            let ratio = tableau[i][rhs] / tableau[i][entering];
+            leaving = match leaving {
+                Some(l) => {
+                    let best = tableau[l][rhs] / tableau[l][entering];
+                    if ratio < best - EPSILON || (ratio < best + EPSILON && basis[i] < basis[l]) {
+                        Some(i)
+                    } else {
+                        Some(l)
+                    }
+                }
+                None => Some(i),
+            };
+        }
+
+        let row = match leaving {
+            Some(row) => row,
+            None => return None,
+        };
+        pivot(tableau, row, entering);
+        basis[row] = entering;
+    }
+}
+
+/// Scales the row such that the column is one there, and eliminates the column
+/// from the other rows.
+fn pivot(tableau: &mut [Vec<f64>], row: usize, col: usize) {
+    let p = tableau[row][col];
+    for x in tableau[row].iter_mut() {
+        *x /= p;
+    }
+    let pivot_row = tableau[row].clone();
+    for (i, t_row) in tableau.iter_mut().enumerate() {
+        let factor = t_row[col];
+        if i == row || factor == 0.0 {
+            continue
+        }
+        for (x, &y) in t_row.iter_mut().zip(&pivot_row) {
+            *x -= factor * y;
+        }
+    }
+}
+
+#[test]
+fn solve_prefers_earlier_variables_at_zero() {
+    // x1 = x0 + 10, and nothing else: x0 stays at zero.
+    let mut solver = Solver::new(2);
+    solver.add_constraint(Expr::var(1, 0.0), Relation::Eq, &Expr::var(0, 10.0));
+    let values = solver.solve().unwrap();
+    assert!((values[0] - 0.0).abs() < 1e-6);
+    assert!((values[1] - 10.0).abs() < 1e-6);
+
+    // Constraining x1 pushes x0 away from zero.
+    solver.add_constraint(Expr::var(1, 0.0), Relation::Le, &Expr::constant(-5.0));
+    let values = solver.solve().unwrap();
+    assert!((values[0] - -15.0).abs() < 1e-6);
+    assert!((values[1] - -5.0).abs() < 1e-6);
+}
+
+#[test]
+fn solve_handles_inequalities_and_contradictions() {
+    // 2 x0 >= 8, so x0 is 4 at best.
+    let mut solver = Solver::new(1);
+    solver.add_constraint(Expr::var(0, 0.0).scale(2.0), Relation::Ge, &Expr::constant(8.0));
+    let values = solver.solve().unwrap();
+    assert!((values[0] - 4.0).abs() < 1e-6);
+
+    solver.add_constraint(Expr::var(0, 0.0), Relation::Le, &Expr::constant(3.0));
+    assert!(solver.solve().is_none());
+}
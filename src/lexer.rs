@@ -34,10 +34,12 @@ pub enum Token<'a> {
     Color(&'a str),
     Number(&'a str),
     Ident(&'a str),
+    StringPrefix(&'a str),
 
     KwAt,
     KwFunction,
     KwImport,
+    KwLayout,
     KwPut,
     KwReturn,
 
@@ -49,7 +51,10 @@ pub enum Token<'a> {
     Comma,
     Dot,
     Equals,
+    EqualsEquals,
+    GreaterEquals,
     Hat,
+    LessEquals,
     Minus,
     Plus,
     Slash,
@@ -60,6 +65,8 @@ pub enum Token<'a> {
     RParen,
     LBrace,
     RBrace,
+    LBracket,
+    RBracket,
 }
 
 /// Lexes a UTF-8 input file into (start_index, token, past_end_index) tokens.
@@ -143,22 +150,35 @@ impl<'a> Lexer<'a> {
         true
     }
 
+    /// Check whether the first byte at or after an index that is not a space
+    /// or newline is the expected byte.
+    fn is_followed_by(&self, at: usize, expected: u8) -> bool {
+        self.input[at..].iter().find(|&&b| b != b' ' && b != b'\n') == Some(&expected)
+    }
+
     /// Push a single-byte token, and set the start of the next token past it.
     fn push_single(&mut self, at: usize, tok: Token<'a>) {
         self.tokens.push((at, tok, at + 1));
         self.start = at + 1;
     }
 
+    /// Push a two-byte token, and return the base state past it.
+    fn push_double(&mut self, at: usize, tok: Token<'a>) -> Result<(usize, State)> {
+        self.tokens.push((at, tok, at + 2));
+        change_state(at + 2, State::Base)
+    }
+
     /// Lex in the base state until a state change occurs.
     ///
     /// Returns new values for `self.start` and `self.state`.
     fn lex_base(&mut self) -> Result<(usize, State)> {
         for i in self.start..self.input.len() {
             match self.input[i] {
-                // There are two characters that require a brief lookahead:
+                // There are a few characters that require a brief lookahead:
                 // * '/', to find the start of a comment "//".
                 // * '-', to find the start of a raw string "---".
-                // If the lookahead does not match, these characters are matched
+                // * '=', '<' and '>', to find the relations "==", "<=", ">=".
+                // If the lookahead does not match, '/', '-' and '=' are matched
                 // again as single-character tokens further below.
                 b'/' if self.has_at(i + 1, b"/") => {
                     return change_state(i, State::InComment)
@@ -166,6 +186,15 @@ impl<'a> Lexer<'a> {
                 b'-' if self.has_at(i + 1, b"--") => {
                     return change_state(i, State::InRawString)
                 }
+                b'=' if self.has_at(i + 1, b"=") => {
+                    return self.push_double(i, Token::EqualsEquals)
+                }
+                b'<' if self.has_at(i + 1, b"=") => {
+                    return self.push_double(i, Token::LessEquals)
+                }
+                b'>' if self.has_at(i + 1, b"=") => {
+                    return self.push_double(i, Token::GreaterEquals)
+                }
 
                 // A few characters signal a change of state immediately. Note
                 // that only spaces and newlines are considered whitespace.
@@ -203,6 +232,8 @@ impl<'a> Lexer<'a> {
                 b')' => self.push_single(i, Token::RParen),
                 b'{' => self.push_single(i, Token::LBrace),
                 b'}' => self.push_single(i, Token::RBrace),
+                b'[' => self.push_single(i, Token::LBracket),
+                b']' => self.push_single(i, Token::RBracket),
 
                 // If we detect the start of a byte order mark, complain about a
                 // wrong encoding. (No BOMs for UTF-8 either, please.)
@@ -307,11 +338,31 @@ impl<'a> Lexer<'a> {
         // characters, digits are allowed too.
         for i in self.start + 1..self.input.len() {
             if !is_alphanumeric_or_underscore(self.input[i]) {
+                let inner = self.parse_utf8_str(self.start, i).unwrap();
+                let token = match make_keyword_or_ident(inner) {
+                    // 'layout' is a keyword only where it starts a layout
+                    // block, so it remains usable as a variable name.
+                    Token::Ident("layout") if self.is_followed_by(i, b'{') => Token::KwLayout,
+                    token => token,
+                };
+
+                // A known prefix directly followed by a string literal, such
+                // as md"*hi*", is a string prefix rather than an identifier.
+                if let Token::Ident(prefix) = token {
+                    if is_string_prefix(prefix) && self.input[i] == b'"' {
+                        self.tokens.push((self.start, Token::StringPrefix(prefix), i));
+                        return change_state(i, State::InString)
+                    }
+                    if is_string_prefix(prefix) && self.has_at(i, b"---") {
+                        self.tokens.push((self.start, Token::StringPrefix(prefix), i));
+                        return change_state(i, State::InRawString)
+                    }
+                }
+
                 // An identifier consists of alphanumeric characters or
                 // underscores, so at the first one that is not one of those,
                 // change to the base state and re-inspect it.
-                let inner = self.parse_utf8_str(self.start, i).unwrap();
-                self.tokens.push((self.start, make_keyword_or_ident(inner), i));
+                self.tokens.push((self.start, token, i));
                 return change_state(i, State::Base)
             }
         }
@@ -529,6 +580,12 @@ fn make_keyword_or_ident(ident: &str) -> Token {
     }
 }
 
+/// Returns whether the identifier is a prefix for string literals. Other
+/// identifiers stay identifiers when a string follows them directly.
+fn is_string_prefix(ident: &str) -> bool {
+    ident == "md"
+}
+
 /// Detects a few byte order marks and returns an error
 fn make_encoding_error(at: usize, input: &[u8]) -> Error {
     let (message, count) = if input.starts_with(&[0xef, 0xbb, 0xbf]) {
@@ -631,6 +688,26 @@ fn lex_handles_a_raw_string_literal() {
     assert_eq!(tokens[2], (12, Token::Ident("baz"), 15));
 }
 
+#[test]
+fn lex_handles_string_prefixes() {
+    let input = br#"md"bar" put---baz---"#;
+    let tokens = lex(input).unwrap();
+    assert_eq!(tokens.len(), 4);
+    assert_eq!(tokens[0], (0, Token::StringPrefix("md"), 2));
+    assert_eq!(tokens[1], (2, Token::String("\"bar\""), 7));
+    assert_eq!(tokens[2], (8, Token::KwPut, 11));
+    assert_eq!(tokens[3], (11, Token::RawString("---baz---"), 20));
+}
+
+#[test]
+fn lex_handles_unknown_prefixes_as_identifiers() {
+    let input = br#"foo"bar""#;
+    let tokens = lex(input).unwrap();
+    assert_eq!(tokens.len(), 2);
+    assert_eq!(tokens[0], (0, Token::Ident("foo"), 3));
+    assert_eq!(tokens[1], (3, Token::String("\"bar\""), 8));
+}
+
 #[test]
 fn lex_strips_a_comment() {
     let input = b"foo\n// This is comment\nbar";
@@ -676,6 +753,18 @@ fn lex_handles_braces() {
     assert_eq!(tokens[1], (2, Token::RBrace, 3));
 }
 
+#[test]
+fn lex_handles_brackets() {
+    let input = b"[a, b]";
+    let tokens = lex(input).unwrap();
+    assert_eq!(tokens.len(), 5);
+    assert_eq!(tokens[0], (0, Token::LBracket, 1));
+    assert_eq!(tokens[1], (1, Token::Ident("a"), 2));
+    assert_eq!(tokens[2], (2, Token::Comma, 3));
+    assert_eq!(tokens[3], (4, Token::Ident("b"), 5));
+    assert_eq!(tokens[4], (5, Token::RBracket, 6));
+}
+
 #[test]
 fn lex_handles_keywords() {
     let input = b"return the function put at the import";
@@ -690,6 +779,27 @@ fn lex_handles_keywords() {
     assert_eq!(tokens[6], (31, Token::KwImport, 37));
 }
 
+#[test]
+fn lex_handles_layout_keyword() {
+    let input = b"layout = layout\n{ layouts }";
+    let tokens = lex(input).unwrap();
+    assert_eq!(tokens.len(), 6);
+    assert_eq!(tokens[0], (0, Token::Ident("layout"), 6));
+    assert_eq!(tokens[2], (9, Token::KwLayout, 15));
+    assert_eq!(tokens[4], (18, Token::Ident("layouts"), 25));
+}
+
+#[test]
+fn lex_handles_relations() {
+    let input = b"a.left==b<=c >= d=e";
+    let tokens = lex(input).unwrap();
+    assert_eq!(tokens.len(), 11);
+    assert_eq!(tokens[3], (6, Token::EqualsEquals, 8));
+    assert_eq!(tokens[5], (9, Token::LessEquals, 11));
+    assert_eq!(tokens[7], (13, Token::GreaterEquals, 15));
+    assert_eq!(tokens[9], (17, Token::Equals, 18));
+}
+
 #[test]
 fn lex_handles_invalid_utf8() {
     let input = [0x2a, 0xac];
@@ -7,14 +7,20 @@
 
 extern crate freetype;
 extern crate lalrpop_util;
+extern crate unicode_bidi;
+extern crate xi_unicode;
 
 mod builtins;
 mod elements;
 mod fontconfig;
 mod harfbuzz;
+mod hyphenation;
+mod layout;
+mod markup;
 mod parser;
 mod pretty;
 mod rsvg;
+mod sfnt;
 mod types;
 
 pub mod ast;
@@ -0,0 +1,106 @@
+// Pris -- A language for designing slides
+// Copyright 2017 Ruud van Asseldonk
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License version 3. A copy
+// of the License is available in the root of the repository.
+
+//! This module parses the markup of md"..." strings into rich text.
+//!
+//! The markup is a small subset of Markdown:
+//!
+//! * `*emphasis*` is set in italic.
+//! * `**strong**` is set in bold.
+//! * `` `code` `` is set in 'code_font_family'. Inside code, `*` has no
+//!   special meaning.
+//!
+//! A backslash makes the next character literal, so `\*` is an asterisk. In
+//! a normal string literal the backslash must be escaped itself, because the
+//! literal only allows the escapes `\\`, `\"`, `\n` and `\u`. So write
+//! `md"\\*"`, or use a raw string, `md---\*---`.
+//!
+//! Markers toggle a style, so a marker that is not closed applies to the rest
+//! of the string.
+
+use std::mem;
+
+use runtime::Span;
+
+/// Parse markup into spans of rich text.
+pub fn parse(markup: &str) -> Vec<Span> {
+    let mut spans = Vec::new();
+    let mut current = Span::plain(String::new());
+    let mut chars = markup.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        // Every marker starts a new span, if the current one has text.
+        let is_marker = c == '`' || (c == '*' && !current.style.code);
+        if is_marker && !current.text.is_empty() {
+            let style = current.style.clone();
+            spans.push(mem::replace(&mut current, Span { text: String::new(), style: style }));
+        }
+
+        match c {
+            '\\' if !current.style.code => {
+                current.text.push(chars.next().unwrap_or('\\'));
+            }
+            '`' => {
+                current.style.code = !current.style.code;
+            }
+            '*' if !current.style.code => {
+                if chars.peek() == Some(&'*') {
+                    chars.next();
+                    current.style.bold = !current.style.bold;
+                } else {
+                    current.style.italic = !current.style.italic;
+                }
+            }
+            _ => current.text.push(c),
+        }
+    }
+
+    if !current.text.is_empty() {
+        spans.push(current);
+    }
+
+    spans
+}
+
+#[test]
+fn parse_handles_emphasis_strong_and_code() {
+    let spans = parse("a *b* **c** `*d*`");
+    let texts: Vec<&str> = spans.iter().map(|s| &s.text[..]).collect();
+    assert_eq!(texts, vec!["a ", "b", " ", "c", " ", "*d*"]);
+    assert!(spans[1].style.italic && !spans[1].style.bold);
+    assert!(spans[3].style.bold && !spans[3].style.italic);
+    assert!(spans[5].style.code && !spans[5].style.italic);
+}
+
+#[test]
+fn parse_handles_nesting_and_escapes() {
+    let spans = parse("***a*** \\*b");
+    let texts: Vec<&str> = spans.iter().map(|s| &s.text[..]).collect();
+    assert_eq!(texts, vec!["a", " *b"]);
+    assert!(spans[0].style.bold && spans[0].style.italic);
+    assert!(!spans[1].style.bold && !spans[1].style.italic);
+}
+
+#[test]
+fn parse_handles_escapes_in_string_literals() {
+    use lexer::{lex, Token};
+    use parser::unescape_string_literal;
+
+    let tokens = lex(br#"md"\\*b\\*""#).unwrap();
+    assert_eq!(tokens[0].1, Token::StringPrefix("md"));
+    let literal = match tokens[1].1 {
+        Token::String(literal) => literal,
+        _ => panic!("Expected a string literal."),
+    };
+    let spans = parse(&unescape_string_literal(literal).unwrap());
+    assert_eq!(spans.len(), 1);
+    assert_eq!(spans[0].text, "*b*");
+    assert!(!spans[0].style.italic);
+
+    // A single backslash is an invalid escape in the literal.
+    assert!(unescape_string_literal(r#""\*b""#).is_err());
+}
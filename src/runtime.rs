@@ -6,16 +6,23 @@
 // of the License is available in the root of the repository.
 
 use freetype;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::collections::hash_map::Entry;
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
 
 use ast::{FnDef, Idents};
 use builtins;
-use elements::{Color, Element, PlacedElement, Vec2};
+use cairo;
+use cairo::Matrix;
+use elements::{Color, Element, Gradient, Paint, PlacedElement, Vec2};
 use error::{Error, Result};
 use fontconfig;
+use fontconfig::FontFile;
+use harfbuzz;
+use hyphenation::Patterns;
 use pretty::{Formatter, Print};
+use sfnt;
 use types::{LenDim, ValType};
 
 #[derive(Clone)]
@@ -23,7 +30,10 @@ pub enum Val<'a> {
     Num(f64, LenDim), // TODO: Be consistent about abbreviating things.
     Str(String),
     Col(Color),
+    Gradient(Gradient),
     Coord(f64, f64, LenDim),
+    List(Vec<Val<'a>>),
+    Rich(Vec<Span>),
     Frame(Rc<Frame<'a>>),
     FnExtrin(&'a FnDef<'a>),
     FnIntrin(Builtin),
@@ -37,6 +47,12 @@ pub struct Frame<'a> {
     /// of the bounding box are relative to the origin of this frame.
     bounding_box: BoundingBox,
 
+    /// The box that encloses the ink of the elements in this frame. For text,
+    /// this is the union of the glyph extents, which can be smaller or larger
+    /// than the logical bounding box. For other elements, it coincides with
+    /// the bounding box.
+    ink_box: BoundingBox,
+
     /// The anchor of this frame; the position at which elements should be
     /// placed when a frame is adjoined, relative to the origin of this frame.
     anchor: Vec2,
@@ -57,15 +73,81 @@ pub struct BoundingBox {
     pub height: f64,
 }
 
+/// A piece of rich text. The style overrides the text variables of the
+/// environment where the text is typeset.
+#[derive(Clone)]
+pub struct Span {
+    pub text: String,
+    pub style: SpanStyle,
+}
+
+/// Overrides for the text variables, for a span of rich text. Fields that are
+/// not set take their value from the environment.
+#[derive(Clone)]
+pub struct SpanStyle {
+    pub bold: bool,
+    pub italic: bool,
+    pub color: Option<Paint>,
+    pub font_size: Option<f64>,
+
+    /// Whether to typeset the span in 'code_font_family' instead of
+    /// 'font_family'.
+    pub code: bool,
+
+    /// OpenType features to enable in addition to 'font_features', in the
+    /// format of that variable. Later entries take precedence.
+    pub font_features: Vec<String>,
+}
+
 /// A "builtin" function is a function that takes an environment and a vector of
 /// arguments, and produces a new value. We make a wrapper type to be able to
 /// implement a no-op clone on it.
 pub struct Builtin(pub for<'a> fn(&mut FontMap, &Env<'a>, Vec<Val<'a>>) -> Result<Val<'a>>);
 
-/// Keeps track of loaded Freetype fonts, indexed by (family name, style) pairs.
+/// Keeps track of loaded Freetype fonts, indexed by font file and face. Font
+/// files are located through Fontconfig, by font spec. Hyphenation patterns
+/// are kept here too, so they are parsed only once.
 pub struct FontMap {
     freetype: freetype::Library,
-    fonts: HashMap<(String, String), freetype::Face<'static>>,
+    faces: HashMap<FontFile, freetype::Face<'static>>,
+
+    /// Faces of variable fonts set to design coordinates, indexed by font file
+    /// and (axis tag, value bits) pairs.
+    instances: HashMap<(FontFile, Vec<(u32, u32)>), freetype::Face<'static>>,
+
+    files: HashMap<FontSpec, Option<fontconfig::FontMatch>>,
+    fallbacks: HashMap<(FontSpec, char), Option<FontFile>>,
+
+    /// What to do when a font family is not installed.
+    substitution: Substitution,
+
+    /// The font files that text was drawn in, with the glyphs drawn.
+    used: BTreeMap<FontFile, BTreeSet<u64>>,
+
+    /// Parsed hyphenation patterns, indexed by pattern file.
+    patterns: HashMap<PathBuf, Rc<Patterns>>,
+}
+
+/// A font that text was drawn in.
+pub struct UsedFont {
+    pub file: FontFile,
+    pub family: String,
+    pub style: String,
+
+    /// The number of distinct glyphs drawn.
+    pub glyph_count: usize,
+
+    /// The embedding permissions of the font, if it has an OS/2 table.
+    pub embedding: Option<sfnt::Embedding>,
+}
+
+/// What to do when Fontconfig substitutes a font of a different family,
+/// because the requested family is not installed.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Substitution {
+    Allow,
+    Warn,
+    Error,
 }
 
 impl<'a> Val<'a> {
@@ -74,7 +156,10 @@ impl<'a> Val<'a> {
             Val::Num(_, d) => ValType::Num(d),
             Val::Str(..) => ValType::Str,
             Val::Col(..) => ValType::Color,
+            Val::Gradient(..) => ValType::Gradient,
             Val::Coord(_, _, d) => ValType::Coord(d),
+            Val::List(..) => ValType::List,
+            Val::Rich(..) => ValType::Rich,
             Val::Frame(..) => ValType::Frame,
             Val::FnExtrin(..) => ValType::Fn,
             Val::FnIntrin(..) => ValType::Fn,
@@ -87,6 +172,7 @@ impl<'a> Frame<'a> {
         Frame {
             env: Env::new(),
             bounding_box: BoundingBox::empty(),
+            ink_box: BoundingBox::empty(),
             anchor: Vec2::zero(),
             elements: Vec::new(),
         }
@@ -96,6 +182,7 @@ impl<'a> Frame<'a> {
         Frame {
             env: env,
             bounding_box: BoundingBox::empty(),
+            ink_box: BoundingBox::empty(),
             anchor: Vec2::zero(),
             elements: Vec::new(),
         }
@@ -122,6 +209,14 @@ impl<'a> Frame<'a> {
             "height" => Some(Val::Num(self.bounding_box.height, 1)),
             "size" => Some(Val::Coord(self.bounding_box.width, self.bounding_box.height, 1)),
             "offset" => Some(Val::Coord(self.bounding_box.x, self.bounding_box.y, 1)),
+            "top_left" => Some(Val::Coord(self.bounding_box.x, self.bounding_box.y, 1)),
+            "center" => Some(Val::Coord(self.bounding_box.x + 0.5 * self.bounding_box.width,
+                                        self.bounding_box.y + 0.5 * self.bounding_box.height, 1)),
+            "bottom_right" => Some(Val::Coord(self.bounding_box.x + self.bounding_box.width,
+                                              self.bounding_box.y + self.bounding_box.height, 1)),
+            "anchor" => Some(Val::Coord(self.anchor.x, self.anchor.y, 1)),
+            "ink_size" => Some(Val::Coord(self.ink_box.width, self.ink_box.height, 1)),
+            "ink_offset" => Some(Val::Coord(self.ink_box.x, self.ink_box.y, 1)),
             _ => None
         };
 
@@ -157,8 +252,29 @@ impl<'a> Frame<'a> {
         &self.bounding_box
     }
 
+    pub fn get_ink_box(&self) -> &BoundingBox {
+        &self.ink_box
+    }
+
+    /// Extend the bounding box and ink box with a box for which both coincide.
     pub fn union_bounding_box(&mut self, bb: &BoundingBox) {
+        self.union_boxes(bb, bb);
+    }
+
+    /// Extend the bounding box and ink box separately.
+    pub fn union_boxes(&mut self, bb: &BoundingBox, ink: &BoundingBox) {
         self.bounding_box = self.bounding_box.union(bb);
+        self.ink_box = self.ink_box.union(ink);
+    }
+
+    /// Place the elements of another frame at the given position, and extend
+    /// the boxes to include its boxes. This does not change the anchor.
+    pub fn place_frame(&mut self, position: Vec2, frame: &Frame<'a>) {
+        for pe in frame.get_elements() {
+            self.place_element(position + pe.position, pe.element.clone());
+        }
+        self.union_boxes(&frame.get_bounding_box().offset(position),
+                         &frame.get_ink_box().offset(position));
     }
 
     pub fn place_element(&mut self, position: Vec2, elem: Element) {
@@ -178,16 +294,102 @@ impl<'a> Env<'a> {
         // The default font is "sans roman", which is usually DejaVu Sans Book.
         bindings.insert("font_family", Val::Str("sans".to_string()));
         bindings.insert("font_style", Val::Str("roman".to_string()));
+        // Explicit font properties, which are inferred from 'font_style' when
+        // "auto". They select the font file, and set the axes of variable
+        // fonts, along with any axes in 'font_variations', e.g. "wght=650".
+        bindings.insert("font_weight", Val::Str("auto".to_string()));
+        bindings.insert("font_stretch", Val::Str("auto".to_string()));
+        bindings.insert("font_slant", Val::Str("auto".to_string()));
+        bindings.insert("font_variations", Val::Str("".to_string()));
+        // A font file to use instead of looking up the family and style, or
+        // "auto" to look them up.
+        bindings.insert("font_file", Val::Str("auto".to_string()));
+        // The font for code spans in markup strings, such as md"`x`".
+        bindings.insert("code_font_family", Val::Str("monospace".to_string()));
+        // OpenType features to apply when shaping, in Harfbuzz syntax, e.g.
+        // "smcp, -liga". Old-style numerals are enabled by default.
+        bindings.insert("font_features", Val::Str("onum".to_string()));
+        bindings.insert("fill_circle", Val::FnIntrin(Builtin(builtins::fill_circle)));
+        bindings.insert("fill_polygon", Val::FnIntrin(Builtin(builtins::fill_polygon)));
         bindings.insert("fill_rectangle", Val::FnIntrin(Builtin(builtins::fill_rectangle)));
         bindings.insert("text_align", Val::Str("left".to_string()));
+        // How 'hstack' and 'vstack' align frames across the stacking
+        // direction: "start", "center", "end", or "baseline" to align origins.
+        bindings.insert("stack_align", Val::Str("start".to_string()));
+        // How 'grid' aligns frames in their cells, horizontally and vertically,
+        // with the same values as 'stack_align', or a list with a value for
+        // every cell. Rows are as high as their highest cell when
+        // 'grid_row_height' is "auto", or it is a length, or a list of those
+        // for the first rows.
+        bindings.insert("cell_align", Val::Str("start".to_string()));
+        bindings.insert("cell_valign", Val::Str("start".to_string()));
+        bindings.insert("grid_row_height", Val::Str("auto".to_string()));
+        // The paragraph direction for bidirectional text: "auto", "ltr", "rtl".
+        bindings.insert("text_direction", Val::Str("auto".to_string()));
+        // The ISO 15924 script and BCP 47 language to shape text with, e.g.
+        // "Cyrl" and "sr". They are detected from the text when "auto".
+        bindings.insert("text_script", Val::Str("auto".to_string()));
+        bindings.insert("text_language", Val::Str("auto".to_string()));
+        // Either "none", "auto" to use patterns for the text language, or the
+        // path to a TeX hyphenation pattern file.
+        bindings.insert("hyphenate", Val::Str("none".to_string()));
+        // The total width of the text set by 'columns' including the gaps,
+        // 0.8w by default, and whether text that does not fit is an "error",
+        // or should "continue" elsewhere.
+        bindings.insert("columns_width", Val::Num(1536.0, 1));
+        bindings.insert("column_overflow", Val::Str("error".to_string()));
+        // The bullets of 'bullets' per nesting level, and what follows the
+        // number in 'numbered'. Nested items are indented by 'list_indent',
+        // and the text of an item starts 'list_hanging_indent' after its
+        // marker. Items are wrapped at 'list_width', unless it is "auto".
+        let list_markers = vec!["\u{2022}", "\u{2013}", "\u{25e6}"];
+        let list_markers = list_markers.into_iter().map(|m| Val::Str(m.to_string())).collect();
+        bindings.insert("list_markers", Val::List(list_markers));
+        bindings.insert("list_number_suffix", Val::Str(".".to_string()));
+        bindings.insert("list_indent", Val::Num(76.8, 1));
+        bindings.insert("list_hanging_indent", Val::Num(76.8, 1));
+        bindings.insert("list_item_spacing", Val::Num(0.0, 1));
+        bindings.insert("list_width", Val::Str("auto".to_string()));
+        // Lines along the text, "none" or "single", and a background color,
+        // or "none" for no background.
+        bindings.insert("underline", Val::Str("none".to_string()));
+        bindings.insert("strikethrough", Val::Str("none".to_string()));
+        bindings.insert("highlight_color", Val::Str("none".to_string()));
         bindings.insert("line_height", Val::Num(128.0, 1));
         bindings.insert("line_width", Val::Num(10.8, 1));
         bindings.insert("color", Val::Col(Color::new(0.0, 0.0, 0.0)));
+        bindings.insert("align", Val::FnIntrin(Builtin(builtins::align)));
+        bindings.insert("align_to", Val::FnIntrin(Builtin(builtins::align_to)));
+        bindings.insert("box", Val::FnIntrin(Builtin(builtins::boxed)));
+        bindings.insert("bold", Val::FnIntrin(Builtin(builtins::bold)));
+        bindings.insert("bullets", Val::FnIntrin(Builtin(builtins::bullets)));
+        bindings.insert("center", Val::FnIntrin(Builtin(builtins::center)));
+        bindings.insert("clip", Val::FnIntrin(Builtin(builtins::clip)));
+        bindings.insert("columns", Val::FnIntrin(Builtin(builtins::columns)));
         bindings.insert("fit", Val::FnIntrin(Builtin(builtins::fit)));
+        bindings.insert("grid", Val::FnIntrin(Builtin(builtins::grid)));
+        bindings.insert("hstack", Val::FnIntrin(Builtin(builtins::hstack)));
         bindings.insert("image", Val::FnIntrin(Builtin(builtins::image)));
+        bindings.insert("italic", Val::FnIntrin(Builtin(builtins::italic)));
         bindings.insert("line", Val::FnIntrin(Builtin(builtins::line)));
+        bindings.insert("load_font", Val::FnIntrin(Builtin(builtins::load_font)));
+        bindings.insert("mirror", Val::FnIntrin(Builtin(builtins::mirror)));
+        bindings.insert("numbered", Val::FnIntrin(Builtin(builtins::numbered)));
+        bindings.insert("pad", Val::FnIntrin(Builtin(builtins::pad)));
+        bindings.insert("paragraph", Val::FnIntrin(Builtin(builtins::paragraph)));
+        bindings.insert("rotate", Val::FnIntrin(Builtin(builtins::rotate)));
+        bindings.insert("rounded_rectangle", Val::FnIntrin(Builtin(builtins::rounded_rectangle)));
+        bindings.insert("scale", Val::FnIntrin(Builtin(builtins::scale)));
+        bindings.insert("skew", Val::FnIntrin(Builtin(builtins::skew)));
+        bindings.insert("linear_gradient", Val::FnIntrin(Builtin(builtins::linear_gradient)));
+        bindings.insert("radial_gradient", Val::FnIntrin(Builtin(builtins::radial_gradient)));
         bindings.insert("str", Val::FnIntrin(Builtin(builtins::str)));
         bindings.insert("t", Val::FnIntrin(Builtin(builtins::t)));
+        bindings.insert("vstack", Val::FnIntrin(Builtin(builtins::vstack)));
+        bindings.insert("with_anchor", Val::FnIntrin(Builtin(builtins::with_anchor)));
+        bindings.insert("with_color", Val::FnIntrin(Builtin(builtins::with_color)));
+        bindings.insert("with_features", Val::FnIntrin(Builtin(builtins::with_features)));
+        bindings.insert("with_size", Val::FnIntrin(Builtin(builtins::with_size)));
         Env { bindings: bindings }
     }
 
@@ -243,6 +445,18 @@ impl<'a> Env<'a> {
         }
     }
 
+    /// Look up a color or gradient, to use as the source for filling or
+    /// stroking.
+    pub fn lookup_paint(&self, idents: &Idents<'a>) -> Result<Paint> {
+        match self.lookup(idents)? {
+            Val::Col(col) => Ok(Paint::Solid(col)),
+            Val::Gradient(grad) => Ok(Paint::Gradient(grad)),
+            other => Err(Error::var_type_one_of(idents,
+                                                &[ValType::Color, ValType::Gradient],
+                                                other.get_type())),
+        }
+    }
+
     pub fn lookup_str(&self, idents: &Idents<'a>) -> Result<String> {
         match self.lookup(idents)? {
             Val::Str(s) => Ok(s),
@@ -300,6 +514,21 @@ impl BoundingBox {
         }
     }
 
+    /// Returns the overlap of two boxes. If they do not overlap, the result
+    /// has zero width or height.
+    pub fn intersect(&self, other: &BoundingBox) -> BoundingBox {
+        let x0 = self.x.max(other.x);
+        let y0 = self.y.max(other.y);
+        let x1 = (self.x + self.width).min(other.x + other.width);
+        let y1 = (self.y + self.height).min(other.y + other.height);
+        BoundingBox {
+            x: x0,
+            y: y0,
+            width: (x1 - x0).max(0.0),
+            height: (y1 - y0).max(0.0),
+        }
+    }
+
     pub fn offset(&self, offset: Vec2) -> BoundingBox {
         BoundingBox {
             x: self.x + offset.x,
@@ -309,16 +538,42 @@ impl BoundingBox {
         }
     }
 
-    pub fn scale(&self, scale: f64) -> BoundingBox {
+    /// Returns the smallest axis-aligned box that contains this box after
+    /// applying the transform.
+    pub fn transform(&self, matrix: &Matrix) -> BoundingBox {
+        let corners = [
+            matrix.transform_point(self.x, self.y),
+            matrix.transform_point(self.x + self.width, self.y),
+            matrix.transform_point(self.x, self.y + self.height),
+            matrix.transform_point(self.x + self.width, self.y + self.height),
+        ];
+        let (mut x0, mut y0) = corners[0];
+        let (mut x1, mut y1) = corners[0];
+        for &(x, y) in &corners[1..] {
+            x0 = x0.min(x);
+            y0 = y0.min(y);
+            x1 = x1.max(x);
+            y1 = y1.max(y);
+        }
         BoundingBox {
-            x: self.x * scale,
-            y: self.y * scale,
-            width: self.width * scale,
-            height: self.height * scale,
+            x: x0,
+            y: y0,
+            width: x1 - x0,
+            height: y1 - y0,
         }
     }
 }
 
+#[test]
+fn bounding_box_transform_contains_rotated_corners() {
+    use std::f64::consts::PI;
+    let bb = BoundingBox::sized(2.0, 1.0).transform(&Matrix::rotate(0.5 * PI));
+    assert!((bb.x + 1.0).abs() < 1e-12);
+    assert!(bb.y.abs() < 1e-12);
+    assert!((bb.width - 1.0).abs() < 1e-12);
+    assert!((bb.height - 2.0).abs() < 1e-12);
+}
+
 impl Clone for Builtin {
     fn clone(&self) -> Builtin {
         let Builtin(x) = *self;
@@ -326,36 +581,293 @@ impl Clone for Builtin {
     }
 }
 
+impl Span {
+    /// Returns a span that takes its style from the environment.
+    pub fn plain(text: String) -> Span {
+        let style = SpanStyle {
+            bold: false,
+            italic: false,
+            color: None,
+            font_size: None,
+            code: false,
+            font_features: Vec::new(),
+        };
+        Span {
+            text: text,
+            style: style,
+        }
+    }
+}
+
+/// The slant of a font.
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
+pub enum Slant {
+    Roman,
+    Italic,
+    Oblique,
+}
+
+/// The properties that select a font from the installed fonts. The weight and
+/// slant are inferred from the words of the style when they are not set.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct FontSpec {
+    pub family: String,
+    pub style: String,
+
+    /// The weight on the OpenType scale, where 400 is regular and 700 is bold.
+    pub weight: Option<i32>,
+
+    /// The width as a percentage of the normal width.
+    pub stretch: Option<i32>,
+
+    pub slant: Option<Slant>,
+}
+
+/// Returns the weight on the OpenType scale for a name such as "bold".
+pub fn weight_from_name(name: &str) -> Option<i32> {
+    match name {
+        "thin" => Some(100),
+        "extralight" | "ultralight" => Some(200),
+        "light" => Some(300),
+        "regular" | "normal" => Some(400),
+        "medium" => Some(500),
+        "semibold" | "demibold" => Some(600),
+        "bold" => Some(700),
+        "extrabold" | "ultrabold" => Some(800),
+        "black" | "heavy" => Some(900),
+        _ => None,
+    }
+}
+
+impl FontSpec {
+    /// Returns the weight, or the heaviest weight named in the style, if any.
+    pub fn weight(&self) -> Option<i32> {
+        self.weight.or_else(|| {
+            self.style.split_whitespace().filter_map(|w| weight_from_name(&w.to_lowercase())).max()
+        })
+    }
+
+    /// Returns the slant, or the slant named in the style, if any.
+    pub fn slant(&self) -> Option<Slant> {
+        self.slant.or_else(|| {
+            self.style.split_whitespace().filter_map(|w| match w.to_lowercase().as_ref() {
+                "italic" => Some(Slant::Italic),
+                "oblique" => Some(Slant::Oblique),
+                _ => None,
+            }).last()
+        })
+    }
+}
+
+/// Returns whether a family name is a generic family, which Fontconfig
+/// resolves to an installed family, rather than the name of a font.
+fn is_generic_family(family: &str) -> bool {
+    match family.to_lowercase().as_ref() {
+        "sans" | "sans-serif" | "serif" | "mono" | "monospace" | "cursive" |
+        "fantasy" | "emoji" | "math" | "system-ui" => true,
+        _ => false,
+    }
+}
+
+/// Returns the Fontconfig query for a font.
+///
+/// Fonts name their styles differently, e.g. "Bold Italic" or "Bold Oblique".
+/// Fontconfig prefers an exact style name, but when no font has one, it falls
+/// back to the weight, width, and slant. So request those too.
+fn font_query(font: &FontSpec) -> String {
+    let mut query = font.family.clone();
+    query.push_str(":style=");
+    query.push_str(&font.style);
+    if let Some(weight) = font.weight() {
+        query.push_str(&format!(":weight={}", fontconfig::weight_from_opentype(weight)));
+    }
+    // The Fontconfig width is a percentage of the normal width already.
+    if let Some(stretch) = font.stretch {
+        query.push_str(&format!(":width={}", stretch));
+    }
+    match font.slant() {
+        Some(Slant::Roman) => query.push_str(":slant=roman"),
+        Some(Slant::Italic) => query.push_str(":slant=italic"),
+        Some(Slant::Oblique) => query.push_str(":slant=oblique"),
+        None => {}
+    }
+    query
+}
+
+#[test]
+fn font_query_requests_weight_and_slant_for_emphasis() {
+    let mut font = FontSpec {
+        family: "Cantarell".to_string(),
+        style: "roman".to_string(),
+        weight: None,
+        stretch: None,
+        slant: None,
+    };
+    assert_eq!(font_query(&font), "Cantarell:style=roman");
+
+    font.style = "Bold Italic".to_string();
+    assert_eq!(font_query(&font), "Cantarell:style=Bold Italic:weight=200:slant=italic");
+
+    // Explicit properties take precedence over the style.
+    font.weight = Some(300);
+    font.stretch = Some(75);
+    font.slant = Some(Slant::Roman);
+    assert_eq!(font_query(&font), "Cantarell:style=Bold Italic:weight=50:width=75:slant=roman");
+}
+
 impl FontMap {
     pub fn new() -> FontMap {
         FontMap {
             freetype: freetype::Library::init().expect("Failed to initialize Freetype."),
-            fonts: HashMap::new(),
+            faces: HashMap::new(),
+            instances: HashMap::new(),
+            files: HashMap::new(),
+            fallbacks: HashMap::new(),
+            substitution: Substitution::Warn,
+            used: BTreeMap::new(),
+            patterns: HashMap::new(),
         }
     }
 
-    pub fn get(&mut self, family: &str, style: &str) -> Option<&mut freetype::Face<'static>> {
-        let key = (family.to_string(), style.to_string());
+    /// Registers the fonts in a directory with Fontconfig. They take priority
+    /// over installed fonts of the same family. Returns false if the
+    /// directory could not be read.
+    pub fn add_font_dir(&mut self, dir: &Path) -> bool {
+        // Lookups may resolve differently with the new fonts.
+        self.files.clear();
+        self.fallbacks.clear();
+        fontconfig::add_font_dir(dir)
+    }
 
-        let entry = match self.fonts.entry(key) {
-            Entry::Occupied(x) => return Some(x.into_mut()),
-            Entry::Vacant(x) => x,
+    /// Registers a font file with Fontconfig, like `add_font_dir`. Returns
+    /// the family name of the font, or `None` if the file is not a font.
+    pub fn add_font_file(&mut self, font_file: &Path) -> Option<String> {
+        self.files.clear();
+        self.fallbacks.clear();
+        if fontconfig::add_font_file(font_file) {
+            self.get(&FontFile::new(font_file.to_path_buf())).family_name()
+        } else {
+            None
+        }
+    }
+
+    pub fn set_substitution(&mut self, substitution: Substitution) {
+        self.substitution = substitution;
+    }
+
+    /// Returns the font file for the given font spec. If the family is not
+    /// installed, Fontconfig substitutes a font of another family, which is
+    /// allowed, reported, or an error, depending on the substitution mode.
+    pub fn locate(&mut self, font: &FontSpec) -> Result<FontFile> {
+        let is_new = !self.files.contains_key(font);
+        if is_new {
+            let found = fontconfig::get_font_location(&font_query(font));
+            self.files.insert(font.clone(), found);
+        }
+
+        let found = match self.files[font] {
+            Some(ref found) => found.clone(),
+            None => return Err(Error::missing_font(font.family.clone(), font.style.clone())),
+        };
+
+        // Generic families such as "sans" always resolve to another family.
+        if found.has_family || is_generic_family(&font.family) {
+            return Ok(found.file)
+        }
+
+        match self.substitution {
+            Substitution::Allow => Ok(found.file),
+            Substitution::Warn => {
+                // Warn only once per font.
+                if is_new {
+                    eprintln!("Warning: the font '{} {}' cannot be found, using '{}' instead.",
+                              font.family, font.style, found.family);
+                }
+                Ok(found.file)
+            }
+            Substitution::Error => {
+                Err(Error::substituted_font(font.family.clone(), font.style.clone(), found.family))
+            }
+        }
+    }
+
+    /// Returns a font file to set the character in, for when the font for the
+    /// given font spec does not contain it. Returns `None` if no installed
+    /// font contains the character.
+    pub fn locate_fallback(&mut self, font: &FontSpec, c: char) -> Option<FontFile> {
+        let key = (font.clone(), c);
+        if let Some(result) = self.fallbacks.get(&key) {
+            return result.clone()
+        }
+
+        let query = font_query(font);
+        let result = match fontconfig::get_font_location_for_char(&query, c) {
+            Some(ref file) if self.has_glyph(file, c) => Some(file.clone()),
+            _ => None,
         };
 
-        // We don't have the font already, look up the file and load it with
-        // Freetype.
+        self.fallbacks.insert(key, result.clone());
+        result
+    }
 
-        let mut query = family.to_string();
-        query.push_str(":style=");
-        query.push_str(style);
-        let font_fname = match fontconfig::get_font_location(&query) {
-            Some(fname) => fname,
+    /// Returns whether the font in the given file has a glyph for the
+    /// character.
+    pub fn has_glyph(&mut self, font_file: &FontFile, c: char) -> bool {
+        self.get(font_file).get_char_index(c as usize) != 0
+    }
+
+    /// Record that glyphs were drawn in the font file.
+    pub fn mark_used(&mut self, font_file: &FontFile, glyphs: &[cairo::Glyph]) {
+        if !self.used.contains_key(font_file) {
+            self.used.insert(font_file.clone(), BTreeSet::new());
+        }
+        let indices = self.used.get_mut(font_file).unwrap();
+        indices.extend(glyphs.iter().map(|g| g.index()));
+    }
+
+    /// Returns the fonts that text was drawn in, ordered by file.
+    pub fn used_fonts(&mut self) -> Vec<UsedFont> {
+        let used: Vec<(FontFile, usize)> = self.used.iter().map(|(f, gs)| (f.clone(), gs.len())).collect();
+        used.into_iter().map(|(file, glyph_count)| {
+            let ft_face = self.get(&file);
+            UsedFont {
+                family: ft_face.family_name().unwrap_or(String::new()),
+                style: ft_face.style_name().unwrap_or(String::new()),
+                glyph_count: glyph_count,
+                embedding: sfnt::embedding(ft_face),
+                file: file,
+            }
+        }).collect()
+    }
+
+    /// Returns the hyphenation patterns in a file, loading them if necessary.
+    /// Returns `None` if the file cannot be read.
+    pub fn get_patterns(&mut self, path: &Path) -> Option<Rc<Patterns>> {
+        if let Some(patterns) = self.patterns.get(path) {
+            return Some(patterns.clone())
+        }
+        let patterns = match Patterns::load(path) {
+            Some(patterns) => Rc::new(patterns),
             None => return None,
         };
+        self.patterns.insert(path.to_path_buf(), patterns.clone());
+        Some(patterns)
+    }
 
-        let ft_face = self.freetype
-            .new_face(font_fname, 0)
-            .expect("Failed to load font using Freetype.");
+    /// Loads the face in a font file with Freetype, if it is not loaded
+    /// already. Returns an error if the file is not a font.
+    pub fn load(&mut self, font_file: &FontFile) -> Result<()> {
+        if self.faces.contains_key(font_file) {
+            return Ok(())
+        }
+
+        let ft_face = match self.freetype.new_face(&font_file.path, font_file.index) {
+            Ok(ft_face) => ft_face,
+            Err(..) => {
+                let msg = format!("Cannot load '{}', it is not a font file.", font_file.description());
+                return Err(Error::Other(msg))
+            }
+        };
 
         // Set a standard size and DPI, so the Harfbuzz output will be relative
         // to this size, and we can scale ourselves when necessary.
@@ -363,8 +875,45 @@ impl FontMap {
         // Freetype bug tracker.
         ft_face.set_char_size(0, 1000, 72, 72).unwrap();
 
-        let ft_face_ref = entry.insert(ft_face);
-        Some(ft_face_ref)
+        self.faces.insert(font_file.clone(), ft_face);
+        Ok(())
+    }
+
+    /// Returns the Freetype face for a font file, loading it if necessary.
+    /// Font files that do not come from Fontconfig must be checked with
+    /// `load` first.
+    pub fn get(&mut self, font_file: &FontFile) -> &mut freetype::Face<'static> {
+        self.load(font_file).expect("Failed to load font using Freetype.");
+        self.faces.get_mut(font_file).unwrap()
+    }
+
+    /// Returns the Freetype face for a font file, set to the given design
+    /// coordinates if it is a variable font. For other fonts, or without
+    /// variations, this is the same face that `get` returns.
+    pub fn get_instance(&mut self,
+                        font_file: &FontFile,
+                        variations: &[harfbuzz::Variation])
+                        -> &mut freetype::Face<'static> {
+        if variations.len() == 0 || !sfnt::is_variable(self.get(font_file)) {
+            return self.get(font_file)
+        }
+
+        // Floats are not hashable, but their bits are.
+        let axes = variations.iter().map(|v| (v.tag(), v.value().to_bits())).collect();
+        let entry = match self.instances.entry((font_file.clone(), axes)) {
+            Entry::Occupied(x) => return x.into_mut(),
+            Entry::Vacant(x) => x,
+        };
+
+        // Every instance needs a face of its own, because Cairo and Harfbuzz
+        // read the design coordinates from the face.
+        let mut ft_face = self.freetype
+            .new_face(&font_file.path, font_file.index)
+            .expect("Failed to load font using Freetype.");
+        ft_face.set_char_size(0, 1000, 72, 72).unwrap();
+        sfnt::set_variations(&self.freetype, &mut ft_face, variations);
+
+        entry.insert(ft_face)
     }
 }
 
@@ -392,6 +941,15 @@ impl<'a> Print for Val<'a> {
                 f.print(col.b);
                 f.print(") : color");
             }
+            Val::Gradient(ref grad) => {
+                match *grad {
+                    Gradient::Linear { .. } => f.print("linear"),
+                    Gradient::Radial { .. } => f.print("radial"),
+                }
+                f.print(" gradient with ");
+                f.print(grad.stops().len());
+                f.print(" stops");
+            }
             Val::Coord(x, y, d) => {
                 f.print("(");
                 f.print(x);
@@ -400,6 +958,26 @@ impl<'a> Print for Val<'a> {
                 f.print(") : coord of ");
                 print_unit(f, d);
             }
+            Val::List(ref elems) => {
+                f.print("[");
+                let mut first = true;
+                for elem in elems {
+                    if !first { f.print(", "); }
+                    f.print(elem);
+                    first = false;
+                }
+                f.print("]");
+            }
+            Val::Rich(ref spans) => {
+                let mut first = true;
+                for span in spans {
+                    if !first { f.print(" ~ "); }
+                    f.print("\"");
+                    f.print(&span.text[..]); // TODO: Escaping.
+                    f.print("\"");
+                    first = false;
+                }
+            }
             Val::Frame(ref frame) => {
                 f.print(frame);
             }
@@ -419,7 +997,10 @@ impl Print for ValType {
             ValType::Num(d) => print_unit(f, d),
             ValType::Str => f.print("str"),
             ValType::Color => f.print("color"),
+            ValType::Gradient => f.print("gradient"),
             ValType::Coord(d) => { f.print("coord of "); print_unit(f, d); }
+            ValType::List => f.print("list"),
+            ValType::Rich => f.print("rich text"),
             ValType::Frame => f.print("frame"),
             ValType::Fn => f.print("function"),
         }
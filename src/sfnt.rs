@@ -0,0 +1,191 @@
+// Pris -- A language for designing slides
+// Copyright 2017 Ruud van Asseldonk
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License version 3. A copy
+// of the License is available in the root of the repository.
+
+//! This module exposes parts of the FreeType API for SFNT fonts that the
+//! freetype crate does not cover: values from the OS/2 table, and the design
+//! axes of variable fonts.
+
+use freetype;
+use freetype::freetype_sys::{FT_Face, FT_Library};
+use harfbuzz::Variation;
+use std::os::raw::{c_char, c_int, c_long, c_uint, c_ulong, c_void};
+use std::ptr;
+use std::slice;
+
+#[allow(non_camel_case_types)]
+type FT_Sfnt_Tag = c_int;
+
+const FT_SFNT_OS2: FT_Sfnt_Tag = 2;
+
+/// The start of the `TT_OS2` struct, up to the fields we need. We only ever
+/// read it through a pointer owned by FreeType, so the rest can be omitted.
+#[repr(C)]
+#[allow(dead_code, non_snake_case)]
+struct TT_OS2 {
+    version: u16,
+    xAvgCharWidth: i16,
+    usWeightClass: u16,
+    usWidthClass: u16,
+    fsType: u16,
+    ySubscriptXSize: i16,
+    ySubscriptYSize: i16,
+    ySubscriptXOffset: i16,
+    ySubscriptYOffset: i16,
+    ySuperscriptXSize: i16,
+    ySuperscriptYSize: i16,
+    ySuperscriptXOffset: i16,
+    ySuperscriptYOffset: i16,
+    yStrikeoutSize: i16,
+    yStrikeoutPosition: i16,
+}
+
+/// This is `FT_FACE_FLAG_MULTIPLE_MASTERS` in the C API.
+const FT_FACE_FLAG_MULTIPLE_MASTERS: c_long = 1 << 8;
+
+#[allow(non_camel_case_types)]
+type FT_Fixed = c_long;
+
+#[repr(C)]
+#[allow(dead_code)]
+struct FT_Var_Axis {
+    name: *mut c_char,
+    minimum: FT_Fixed,
+    def: FT_Fixed,
+    maximum: FT_Fixed,
+    tag: c_ulong,
+    strid: c_uint,
+}
+
+#[repr(C)]
+#[allow(dead_code)]
+struct FT_MM_Var {
+    num_axis: c_uint,
+    num_designs: c_uint,
+    num_namedstyles: c_uint,
+    axis: *mut FT_Var_Axis,
+    namedstyle: *mut c_void,
+}
+
+#[link(name = "freetype")]
+extern {
+    fn FT_Get_Sfnt_Table(face: FT_Face, tag: FT_Sfnt_Tag) -> *mut c_void;
+    fn FT_Get_MM_Var(face: FT_Face, amaster: *mut *mut FT_MM_Var) -> c_int;
+    fn FT_Done_MM_Var(library: FT_Library, amaster: *mut FT_MM_Var) -> c_int;
+    fn FT_Set_Var_Design_Coordinates(face: FT_Face, num_coords: c_uint, coords: *const FT_Fixed) -> c_int;
+}
+
+fn os2_table<'f>(ft_face: &'f mut freetype::Face<'static>) -> Option<&'f TT_OS2> {
+    unsafe {
+        let table = FT_Get_Sfnt_Table(ft_face.raw_mut(), FT_SFNT_OS2) as *const TT_OS2;
+        table.as_ref()
+    }
+}
+
+/// The embedding permissions of a font, from the 'fsType' field of the OS/2
+/// table.
+#[derive(Copy, Clone, Debug)]
+pub struct Embedding(u16);
+
+impl Embedding {
+    /// Returns why embedding the font violates its license, if it does.
+    ///
+    /// Bit 1 is 'restricted license embedding'. Bits 2 and 3 permit embedding
+    /// for previewing and printing, or for editing. When several are set, the
+    /// least restrictive one applies. Cairo always embeds a subset of the
+    /// outlines, so bit 8, 'no subsetting', and bit 9, 'bitmap embedding
+    /// only', are violated too.
+    pub fn violation(&self) -> Option<&'static str> {
+        if self.0 & 0x000e == 0x0002 {
+            Some("the font must not be embedded")
+        } else if self.0 & 0x0100 != 0 {
+            Some("the font must be embedded in full, but only a subset is embedded")
+        } else if self.0 & 0x0200 != 0 {
+            Some("only bitmaps may be embedded, but the outlines are embedded")
+        } else {
+            None
+        }
+    }
+
+    /// Returns a description of the permissions, such as "installable".
+    pub fn description(&self) -> String {
+        let mut description = match self.0 & 0x000e {
+            0x0000 => "installable",
+            0x0002 => "restricted, must not be embedded",
+            x if x & 0x0008 != 0 => "editable",
+            _ => "preview and print",
+        }.to_string();
+        if self.0 & 0x0100 != 0 {
+            description.push_str(", no subsetting");
+        }
+        if self.0 & 0x0200 != 0 {
+            description.push_str(", bitmap embedding only");
+        }
+        description
+    }
+}
+
+#[test]
+fn embedding_applies_least_restrictive_permission() {
+    assert!(Embedding(0x0000).violation().is_none());
+    assert!(Embedding(0x0002).violation().is_some());
+    assert!(Embedding(0x0006).violation().is_none());
+    assert!(Embedding(0x0108).violation().is_some());
+    assert!(Embedding(0x0200).violation().is_some());
+    assert_eq!(Embedding(0x0006).description(), "preview and print");
+    assert_eq!(Embedding(0x010c).description(), "editable, no subsetting");
+}
+
+/// Returns the embedding permissions of a face. Returns `None` if the font has
+/// no OS/2 table.
+pub fn embedding(ft_face: &mut freetype::Face<'static>) -> Option<Embedding> {
+    os2_table(ft_face).map(|os2| Embedding(os2.fsType))
+}
+
+/// Returns the strikeout stroke of a face as (position of the top of the
+/// stroke above the baseline, thickness), in font units. Returns `None` if the
+/// font has no OS/2 table, for instance because it is a Type 1 font.
+pub fn strikeout_metrics(ft_face: &mut freetype::Face<'static>) -> Option<(i16, i16)> {
+    match os2_table(ft_face) {
+        Some(os2) if os2.yStrikeoutSize > 0 => Some((os2.yStrikeoutPosition, os2.yStrikeoutSize)),
+        _ => None,
+    }
+}
+
+/// Returns whether a face is a variable font, with design axes such as weight.
+pub fn is_variable(ft_face: &freetype::Face<'static>) -> bool {
+    ft_face.raw().face_flags as c_long & FT_FACE_FLAG_MULTIPLE_MASTERS != 0
+}
+
+/// Set the design coordinates of a variable font. Axes that are not listed
+/// keep their default, values are clamped to the range of the axis, and
+/// variations for axes that the font does not have are ignored. When an axis
+/// is listed more than once, the last value wins.
+///
+/// Harfbuzz and Cairo read the coordinates from the face, so this affects
+/// both shaping and rendering.
+pub fn set_variations(library: &freetype::Library,
+                      ft_face: &mut freetype::Face<'static>,
+                      variations: &[Variation]) {
+    unsafe {
+        let mut mm_var: *mut FT_MM_Var = ptr::null_mut();
+        if FT_Get_MM_Var(ft_face.raw_mut(), &mut mm_var) != 0 {
+            return
+        }
+
+        let axes = slice::from_raw_parts((*mm_var).axis, (*mm_var).num_axis as usize);
+        let coords: Vec<FT_Fixed> = axes.iter().map(|axis| {
+            match variations.iter().rev().find(|v| v.tag() as c_ulong == axis.tag) {
+                // Design coordinates are in 16.16 fixed point.
+                Some(v) => ((v.value() as f64 * 65536.0) as FT_Fixed).max(axis.minimum).min(axis.maximum),
+                None => axis.def,
+            }
+        }).collect();
+
+        FT_Set_Var_Design_Coordinates(ft_face.raw_mut(), coords.len() as c_uint, coords.as_ptr());
+        FT_Done_MM_Var(library.raw(), mm_var);
+    }
+}
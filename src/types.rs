@@ -10,7 +10,10 @@ pub enum ValType {
     Num(LenDim),
     Str,
     Color,
+    Gradient,
     Coord(LenDim),
+    List,
+    Rich,
     Frame,
     Fn
 }